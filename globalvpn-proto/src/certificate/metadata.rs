@@ -1,4 +1,12 @@
-use yasna::{ASN1Result, BERDecodable, BERReader, DEREncodable, DERWriter, Tag};
+use crate::certificate::{
+    CertificateError, CertificateResult, DEFAULT_CERTIFICATE_VALIDITY_SECONDS,
+};
+use crate::data::Area;
+use chrono::Duration;
+use std::convert::TryFrom;
+use yasna::{
+    ASN1Error, ASN1ErrorKind, ASN1Result, BERDecodable, BERReader, DEREncodable, DERWriter, Tag,
+};
 
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct NodeMetadata {
@@ -7,6 +15,49 @@ pub struct NodeMetadata {
     /// Time, how long the certificate can be used in a freshly
     /// bootet node that does not contain a warm table yet
     pub maximum_cold_table_seconds: Option<u64>,
+    /// Counter increased by the node for every signed certificate
+    ///
+    /// Orders certificates of the same node which were signed in the same second.
+    pub sequence_number: Option<u64>,
+    /// Area of a regional deployment the node belongs to
+    ///
+    /// Hybrid dictionary nodes only store the certificates of their own area.
+    pub area: Option<Area>,
+    /// Set in the certificate a node publishes when leaving the network
+    ///
+    /// See [`crate::certificate::CertificateData::leave`].
+    pub tombstone: bool,
+}
+
+impl NodeMetadata {
+    /// Validity of a certificate containing this metadata
+    ///
+    /// The certificate has to be valid as long as it may be hold in a warm or cold table.
+    /// Returns `None` if no table time is specified.
+    pub fn validity_seconds(&self) -> Option<u64> {
+        match (
+            self.maximum_warm_table_seconds,
+            self.maximum_cold_table_seconds,
+        ) {
+            (Some(warm), Some(cold)) => Some(warm.max(cold)),
+            (warm, cold) => warm.or(cold),
+        }
+    }
+
+    /// Validity of a certificate containing this metadata
+    ///
+    /// [`DEFAULT_CERTIFICATE_VALIDITY_SECONDS`] is used if no table time is specified.
+    /// Fails if the table time is too long to be represented.
+    pub fn validity(&self) -> CertificateResult<Duration> {
+        let seconds = self
+            .validity_seconds()
+            .unwrap_or(DEFAULT_CERTIFICATE_VALIDITY_SECONDS);
+        i64::try_from(seconds)
+            .ok()
+            .filter(|seconds| *seconds <= Duration::max_value().num_seconds())
+            .map(Duration::seconds)
+            .ok_or(CertificateError::InvalidValidity)
+    }
 }
 
 impl DEREncodable for NodeMetadata {
@@ -22,6 +73,21 @@ impl DEREncodable for NodeMetadata {
                     writer.write_u64(maximum_cold_table_seconds);
                 });
             }
+            if let Some(sequence_number) = self.sequence_number {
+                writer.next().write_tagged(Tag::context(2), |writer| {
+                    writer.write_u64(sequence_number);
+                });
+            }
+            if let Some(area) = &self.area {
+                writer.next().write_tagged(Tag::context(3), |writer| {
+                    writer.write_utf8_string(area.as_str());
+                });
+            }
+            if self.tombstone {
+                writer.next().write_tagged(Tag::context(4), |writer| {
+                    writer.write_bool(true);
+                });
+            }
         });
     }
 }
@@ -35,9 +101,28 @@ impl BERDecodable for NodeMetadata {
             let maximum_cold_table_seconds = reader.read_optional(|reader| {
                 reader.read_tagged(Tag::context(1), |reader| reader.read_u64())
             })?;
+            let sequence_number = reader.read_optional(|reader| {
+                reader.read_tagged(Tag::context(2), |reader| reader.read_u64())
+            })?;
+            let area = reader.read_optional(|reader| {
+                reader.read_tagged(Tag::context(3), |reader| {
+                    reader
+                        .read_utf8string()?
+                        .parse()
+                        .map_err(|_err| ASN1Error::new(ASN1ErrorKind::Invalid))
+                })
+            })?;
+            let tombstone = reader
+                .read_optional(|reader| {
+                    reader.read_tagged(Tag::context(4), |reader| reader.read_bool())
+                })?
+                .unwrap_or(false);
             Ok(NodeMetadata {
                 maximum_warm_table_seconds,
                 maximum_cold_table_seconds,
+                sequence_number,
+                area,
+                tombstone,
             })
         })
     }
@@ -46,6 +131,7 @@ impl BERDecodable for NodeMetadata {
 #[cfg(test)]
 mod tests {
     use crate::certificate::NodeMetadata;
+    use yasna::Tag;
 
     #[test]
     fn test_encode_decode_node_metadata() {
@@ -53,18 +139,51 @@ mod tests {
             NodeMetadata {
                 maximum_warm_table_seconds: Some(12345),
                 maximum_cold_table_seconds: None,
+                sequence_number: None,
+                area: None,
+                tombstone: false,
             },
             NodeMetadata {
                 maximum_warm_table_seconds: None,
                 maximum_cold_table_seconds: Some(12345),
+                sequence_number: None,
+                area: None,
+                tombstone: false,
             },
             NodeMetadata {
                 maximum_warm_table_seconds: None,
                 maximum_cold_table_seconds: None,
+                sequence_number: None,
+                area: None,
+                tombstone: false,
             },
             NodeMetadata {
                 maximum_warm_table_seconds: Some(12345),
                 maximum_cold_table_seconds: Some(54321),
+                sequence_number: None,
+                area: None,
+                tombstone: false,
+            },
+            NodeMetadata {
+                maximum_warm_table_seconds: Some(12345),
+                maximum_cold_table_seconds: None,
+                sequence_number: Some(7),
+                area: None,
+                tombstone: false,
+            },
+            NodeMetadata {
+                maximum_warm_table_seconds: Some(12345),
+                maximum_cold_table_seconds: None,
+                sequence_number: Some(7),
+                area: Some("eu-central".parse().unwrap()),
+                tombstone: false,
+            },
+            NodeMetadata {
+                maximum_warm_table_seconds: Some(12345),
+                maximum_cold_table_seconds: None,
+                sequence_number: Some(8),
+                area: None,
+                tombstone: true,
             },
         ];
 
@@ -74,4 +193,32 @@ mod tests {
             assert_eq!(case, decoded);
         }
     }
+
+    #[test]
+    fn test_validity_seconds() {
+        let metadata = |warm, cold| NodeMetadata {
+            maximum_warm_table_seconds: warm,
+            maximum_cold_table_seconds: cold,
+            sequence_number: None,
+            area: None,
+            tombstone: false,
+        };
+
+        assert_eq!(metadata(None, None).validity_seconds(), None);
+        assert_eq!(metadata(Some(100), None).validity_seconds(), Some(100));
+        assert_eq!(metadata(None, Some(200)).validity_seconds(), Some(200));
+        assert_eq!(metadata(Some(300), Some(200)).validity_seconds(), Some(300));
+    }
+
+    #[test]
+    fn test_decode_invalid_area() {
+        let encoded = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_tagged(Tag::context(3), |writer| {
+                    writer.write_utf8_string("Not An Area");
+                });
+            });
+        });
+        assert!(yasna::decode_der::<NodeMetadata>(&encoded).is_err());
+    }
 }
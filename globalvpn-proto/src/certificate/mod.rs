@@ -41,7 +41,7 @@
 //!     }
 //!
 //!     NodeProxyReachability ::= SEQUENCE {
-//!         proxyAddress            OCTET STRING
+//!         proxyAddress            OCTET STRING (SIZE(32))
 //!         proxyReachability   [0] EXPLICIT SET OF NodeIpReachability
 //!     }
 //! END
@@ -53,8 +53,11 @@
 //! NodeMetadata DEFINITIONS ::= BEGIN
 //!
 //!     NodeMetadata ::= SEQUENCE {
-//!         maximumWarmTableSeconds [0] EXPLICIT INTEGER
-//!         maximumColdTableSeconds [1] EXPLICIT INTEGER
+//!         maximumWarmTableSeconds [0] EXPLICIT INTEGER OPTIONAL
+//!         maximumColdTableSeconds [1] EXPLICIT INTEGER OPTIONAL
+//!         sequenceNumber          [2] EXPLICIT INTEGER OPTIONAL
+//!         area                    [3] EXPLICIT UTF8String OPTIONAL
+//!         tombstone               [4] EXPLICIT BOOLEAN DEFAULT FALSE
 //!     }
 //!
 //! END
@@ -62,14 +65,24 @@
 
 mod metadata;
 mod reachability;
+mod sequence;
+mod validity;
 
 pub use metadata::NodeMetadata;
 pub use reachability::{NodeIpReachability, NodeProxyReachability, NodeReachabilityInformation};
+pub use sequence::SequenceCounter;
+pub use validity::{
+    CertificateValidity, CertificateVersion, Clock, FixedClock, SystemClock, ValidityPolicy,
+    DEFAULT_ALLOWED_CLOCK_SKEW_SECONDS, DEFAULT_CERTIFICATE_VALIDITY_SECONDS,
+};
 
-use chrono::{Duration, Utc};
-use log::{error, warn};
+use crate::data::NodeId;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
 use pem::Pem;
-use rcgen::{Certificate, CertificateParams, CustomExtension, DistinguishedName, KeyPair, RcgenError};
+use rcgen::{
+    Certificate, CertificateParams, CustomExtension, DistinguishedName, KeyPair, RcgenError,
+};
+use ring::signature::{UnparsedPublicKey, ED25519};
 use std::convert::TryFrom;
 use std::net::{SocketAddrV4, SocketAddrV6};
 use x509_parser::der_parser::oid::Oid;
@@ -97,7 +110,7 @@ pub struct ReachabilityInformation {
     /// Proxy node ID
     ///
     /// OID `1.3.6.1.4.1.123456.1.3`
-    pub proxy_node: Option<Vec<u8>>,
+    pub proxy_node: Option<NodeId>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -107,16 +120,71 @@ pub struct CertificateData {
 }
 
 impl CertificateData {
+    /// Certificate data announcing that the node left the network
+    ///
+    /// The reachability information is empty and the metadata carries the tombstone marker.
+    /// The table times are kept, so the tombstone is stored as long as the previous certificate.
+    pub fn leave(&self) -> Self {
+        CertificateData {
+            reachability: NodeReachabilityInformation::default(),
+            metadata: NodeMetadata {
+                tombstone: true,
+                ..self.metadata.clone()
+            },
+        }
+    }
+
+    /// Checks if the certificate announces that the node left the network
+    ///
+    /// Certificates without reachability information are no tombstones,
+    /// e.g. nodes which can only be reached through relays.
+    pub fn is_tombstone(&self) -> bool {
+        self.metadata.tombstone
+    }
+
+    /// Signs the certificate data, starting the validity now
+    ///
+    /// The validity length is taken from the node metadata,
+    /// [`DEFAULT_CERTIFICATE_VALIDITY_SECONDS`] is used if it does not contain a table time.
     pub fn sign(&self, private_key_der: &[u8]) -> CertificateResult<RawCertificate> {
+        self.sign_with_validity(
+            private_key_der,
+            SystemClock.now(),
+            self.metadata.validity()?,
+        )
+    }
+
+    /// Signs the certificate data with an explicit validity window
+    ///
+    /// `not_before` is truncated to whole seconds, as X.509 can not encode fractions.
+    /// Certificates signed in the same second are only ordered by the sequence number
+    /// of the metadata, which is usually taken from a [`SequenceCounter`].
+    /// Fails if the validity is negative or `not_after` can not be represented.
+    pub fn sign_with_validity(
+        &self,
+        private_key_der: &[u8],
+        not_before: DateTime<Utc>,
+        validity: Duration,
+    ) -> CertificateResult<RawCertificate> {
+        let not_before = not_before.with_nanosecond(0).unwrap_or(not_before);
+        if validity < Duration::zero() {
+            return Err(CertificateError::InvalidValidity);
+        }
+        // X.509 times are encoded with four digit years
+        let not_after = not_before
+            .checked_add_signed(validity)
+            .filter(|not_after| not_after.year() <= 9999)
+            .ok_or(CertificateError::InvalidValidity)?;
+
         let mut params = CertificateParams::default();
         params.alg = &rcgen::PKCS_ED25519;
         params.key_pair = Some(KeyPair::from_der(private_key_der)?);
-        params.not_before = Utc::today().and_hms(0, 0, 0);
-        params.not_after = (Utc::today() + Duration::days(7)).and_hms(0, 0, 0);
+        params.not_before = not_before;
+        params.not_after = not_after;
         //params.key_pair =
         //    Some(KeyPair::from_der(private_key_der).map_err(|_err| CertificateError::ParseDer)?);
         params.distinguished_name = {
-            let mut name = DistinguishedName::new();
+            let name = DistinguishedName::new();
             //name.push(DnType::CommonName, "globalvpn self signed node cert");
             name
         };
@@ -141,12 +209,14 @@ impl CertificateData {
             .map_err(|_err| CertificateError::GeneratingCertificate)?;
         Ok(RawCertificate { encoded_der })
     }
-}
 
-impl TryFrom<RawCertificate> for CertificateData {
-    type Error = CertificateError;
-
-    fn try_from(value: RawCertificate) -> Result<Self, Self::Error> {
+    /// Decodes and verifies a certificate using the given validity policy
+    ///
+    /// Returns the [`NodeId`] of the subject together with the certificate data.
+    pub fn decode<C: Clock>(
+        value: &RawCertificate,
+        policy: &ValidityPolicy<C>,
+    ) -> CertificateResult<(NodeId, Self)> {
         let (_, certificate) = x509_parser::parse_x509_certificate(value.der())?;
 
         let certificate_oid = certificate.signature_algorithm.algorithm;
@@ -156,8 +226,27 @@ impl TryFrom<RawCertificate> for CertificateData {
         if certificate_oid != x509_ed25519_oid {
             return Err(CertificateError::InvalidSignatureAlgorithm);
         }
-        // TODO check certificate
-        warn!("X.509 ceritificate verification is not implemented, until x509_parser supports Ed25519");
+
+        // x509_parser does not support Ed25519 yet, so the self signature
+        // is checked against the subject public key using ring directly.
+        let public_key_data = certificate
+            .tbs_certificate
+            .subject_pki
+            .subject_public_key
+            .data;
+        let public_key = UnparsedPublicKey::new(&ED25519, public_key_data);
+        public_key
+            .verify(
+                certificate.tbs_certificate.as_ref(),
+                certificate.signature_value.data,
+            )
+            .map_err(|_err| CertificateError::InvalidSignature)?;
+
+        let validity = &certificate.tbs_certificate.validity;
+        policy.check(
+            Utc.timestamp(validity.not_before.timestamp(), 0),
+            Utc.timestamp(validity.not_after.timestamp(), 0),
+        )?;
 
         let extensions = certificate.tbs_certificate.extensions();
         let reachability = yasna::decode_der(
@@ -166,19 +255,31 @@ impl TryFrom<RawCertificate> for CertificateData {
                 .ok_or(CertificateError::MissingReachabilityInformation)?
                 .value,
         )
-            .map_err(|_err| CertificateError::DecodeReachabilityInformation)?;
+        .map_err(|_err| CertificateError::DecodeReachabilityInformation)?;
         let metadata = yasna::decode_der(
             extensions
                 .get(&Oid::from(OID_GLOBALVPN_X509_METADATA).unwrap())
                 .ok_or(CertificateError::MissingNodeMetadata)?
                 .value,
         )
-            .map_err(|_err| CertificateError::DecodeNodeMetadata)?;
+        .map_err(|_err| CertificateError::DecodeNodeMetadata)?;
 
-        Ok(CertificateData {
-            reachability,
-            metadata,
-        })
+        Ok((
+            NodeId::from_public_key(public_key_data),
+            CertificateData {
+                reachability,
+                metadata,
+            },
+        ))
+    }
+}
+
+impl TryFrom<RawCertificate> for CertificateData {
+    type Error = CertificateError;
+
+    fn try_from(value: RawCertificate) -> Result<Self, Self::Error> {
+        let (_node_id, data) = CertificateData::decode(&value, &ValidityPolicy::default())?;
+        Ok(data)
     }
 }
 
@@ -188,10 +289,56 @@ pub struct RawCertificate {
 }
 
 impl RawCertificate {
+    /// Wraps a DER encoded certificate without checking it
+    pub fn from_der(encoded_der: Vec<u8>) -> Self {
+        RawCertificate { encoded_der }
+    }
+
     pub fn der(&self) -> &[u8] {
         self.encoded_der.as_slice()
     }
 
+    /// NodeId derived from the subject public key
+    ///
+    /// The signature of the certificate is not verified.
+    pub fn node_id(&self) -> CertificateResult<NodeId> {
+        Ok(NodeId::from_public_key(&self.public_key()?))
+    }
+
+    /// Ed25519 subject public key
+    ///
+    /// The signature of the certificate is not verified.
+    pub fn public_key(&self) -> CertificateResult<Vec<u8>> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(self.der())?;
+        Ok(certificate
+            .tbs_certificate
+            .subject_pki
+            .subject_public_key
+            .data
+            .to_vec())
+    }
+
+    /// Sequence number and validity window
+    ///
+    /// The signature of the certificate is not verified.
+    pub fn validity(&self) -> CertificateResult<CertificateValidity> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(self.der())?;
+        let tbs_certificate = &certificate.tbs_certificate;
+        let metadata: NodeMetadata = yasna::decode_der(
+            tbs_certificate
+                .extensions()
+                .get(&Oid::from(OID_GLOBALVPN_X509_METADATA).unwrap())
+                .ok_or(CertificateError::MissingNodeMetadata)?
+                .value,
+        )
+        .map_err(|_err| CertificateError::DecodeNodeMetadata)?;
+        Ok(CertificateValidity {
+            sequence_number: metadata.sequence_number.unwrap_or(0),
+            not_before: Utc.timestamp(tbs_certificate.validity.not_before.timestamp(), 0),
+            not_after: Utc.timestamp(tbs_certificate.validity.not_after.timestamp(), 0),
+        })
+    }
+
     pub fn pem(&self) -> String {
         let pem = Pem {
             tag: "CERTIFICATE".to_string(),
@@ -213,6 +360,18 @@ pub enum CertificateError {
     /// invalid signature algorithm
     #[error("certificate has invalid signature algorithm")]
     InvalidSignatureAlgorithm,
+    /// self signature does not match the subject public key
+    #[error("certificate has invalid signature")]
+    InvalidSignature,
+    /// certificate is not valid yet
+    #[error("certificate is not valid yet")]
+    NotYetValid,
+    /// certificate is expired
+    #[error("certificate is expired")]
+    Expired,
+    /// `not_after` is before `not_before` or the validity can not be represented
+    #[error("certificate has an invalid validity window")]
+    InvalidValidity,
     /// X.509 parsing error
     #[error("decoding X.509: {0}")]
     X509(#[from] x509_parser::nom::Err<X509Error>),
@@ -238,10 +397,13 @@ pub type CertificateResult<T> = Result<T, CertificateError>;
 #[cfg(test)]
 mod tests {
     use crate::certificate::{
-        CertificateData, NodeIpReachability, NodeMetadata, NodeProxyReachability,
-        NodeReachabilityInformation,
+        CertificateData, CertificateError, FixedClock, NodeIpReachability, NodeMetadata,
+        NodeProxyReachability, NodeReachabilityInformation, RawCertificate, ValidityPolicy,
     };
+    use crate::data::NodeId;
+    use chrono::{Duration, TimeZone, Utc};
     use ring::rand::SystemRandom;
+    use ring::signature::KeyPair;
     use std::collections::BTreeSet;
     use std::convert::{TryFrom, TryInto};
 
@@ -258,30 +420,181 @@ mod tests {
                     quic_port: Some(1337),
                 },
             ]
-                .into_iter()
-                .collect(),
+            .into_iter()
+            .collect(),
             proxy_reachability: vec![NodeProxyReachability {
-                proxy_address: vec![123, 34, 34, 212, 43, 93],
+                proxy_address: NodeId::from([123; 32]),
                 proxy_reachability: BTreeSet::new(),
             }]
-                .into_iter()
-                .collect(),
+            .into_iter()
+            .collect(),
         };
 
         let metadata = NodeMetadata {
             maximum_warm_table_seconds: Some(2600),
             maximum_cold_table_seconds: None,
+            sequence_number: Some(3),
+            area: None,
+            tombstone: false,
         };
 
         let certificate_data = CertificateData {
             reachability,
             metadata,
         };
-        let mut rng = SystemRandom::new();
-        let private_key = ring::signature::Ed25519KeyPair::generate_pkcs8(&mut rng).unwrap();
+        let rng = SystemRandom::new();
+        let private_key = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
 
         let encoded = certificate_data.sign(private_key.as_ref()).unwrap();
+        assert_eq!(encoded.validity().unwrap().sequence_number, 3);
         let decoded: CertificateData = encoded.try_into().unwrap();
         assert_eq!(certificate_data, decoded);
     }
+
+    #[test]
+    fn order_by_sequence_number() {
+        let rng = SystemRandom::new();
+        let private_key = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let not_before = Utc.ymd(2021, 1, 1).and_hms(12, 0, 0);
+        let sign = |sequence_number| {
+            CertificateData {
+                reachability: NodeReachabilityInformation::default(),
+                metadata: NodeMetadata {
+                    sequence_number,
+                    ..NodeMetadata::default()
+                },
+            }
+            .sign_with_validity(private_key.as_ref(), not_before, Duration::hours(1))
+            .unwrap()
+            .validity()
+            .unwrap()
+        };
+
+        assert_eq!(sign(None).sequence_number, 0);
+        assert!(sign(Some(1)).is_newer_than(&sign(None)));
+        assert!(sign(Some(2)).is_newer_than(&sign(Some(1))));
+        assert!(!sign(Some(2)).is_newer_than(&sign(Some(2))));
+    }
+
+    #[test]
+    fn node_id_from_public_key() {
+        let certificate_data = CertificateData {
+            reachability: NodeReachabilityInformation::default(),
+            metadata: NodeMetadata::default(),
+        };
+        let rng = SystemRandom::new();
+        let private_key = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = ring::signature::Ed25519KeyPair::from_pkcs8(private_key.as_ref()).unwrap();
+        let expected = NodeId::from_public_key(key_pair.public_key().as_ref());
+
+        let encoded = certificate_data.sign(private_key.as_ref()).unwrap();
+        assert_eq!(encoded.node_id().unwrap(), expected);
+
+        let (node_id, decoded) =
+            CertificateData::decode(&encoded, &ValidityPolicy::default()).unwrap();
+        assert_eq!(node_id, expected);
+        assert_eq!(decoded, certificate_data);
+    }
+
+    #[test]
+    fn reject_invalid_signature() {
+        let certificate_data = CertificateData {
+            reachability: NodeReachabilityInformation::default(),
+            metadata: NodeMetadata::default(),
+        };
+        let rng = SystemRandom::new();
+        let private_key = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+
+        let encoded = certificate_data.sign(private_key.as_ref()).unwrap();
+        // the signature is the last element of the certificate
+        let mut encoded_der = encoded.encoded_der;
+        *encoded_der.last_mut().unwrap() ^= 0x01;
+        let tampered = RawCertificate { encoded_der };
+
+        assert!(matches!(
+            CertificateData::try_from(tampered),
+            Err(CertificateError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn reject_outside_validity_window() {
+        let certificate_data = CertificateData {
+            reachability: NodeReachabilityInformation::default(),
+            metadata: NodeMetadata::default(),
+        };
+        let rng = SystemRandom::new();
+        let private_key = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+
+        let not_before = Utc.ymd(2021, 1, 1).and_hms(12, 0, 0);
+        let encoded = certificate_data
+            .sign_with_validity(private_key.as_ref(), not_before, Duration::hours(1))
+            .unwrap();
+        let policy = |now| ValidityPolicy::new(FixedClock(now));
+
+        let validity = encoded.validity().unwrap();
+        assert_eq!(validity.not_before, not_before);
+        assert_eq!(validity.not_after, not_before + Duration::hours(1));
+        assert_eq!(
+            CertificateData::decode(&encoded, &policy(not_before + Duration::minutes(30)))
+                .unwrap()
+                .1,
+            certificate_data
+        );
+        assert!(matches!(
+            CertificateData::decode(&encoded, &policy(not_before - Duration::hours(1))),
+            Err(CertificateError::NotYetValid)
+        ));
+        assert!(matches!(
+            CertificateData::decode(&encoded, &policy(not_before + Duration::hours(2))),
+            Err(CertificateError::Expired)
+        ));
+    }
+
+    #[test]
+    fn reject_unrepresentable_validity() {
+        let rng = SystemRandom::new();
+        let private_key = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let sign = |warm_seconds| {
+            CertificateData {
+                reachability: NodeReachabilityInformation::default(),
+                metadata: NodeMetadata {
+                    maximum_warm_table_seconds: Some(warm_seconds),
+                    ..NodeMetadata::default()
+                },
+            }
+            .sign(private_key.as_ref())
+        };
+
+        assert!(sign(3600).is_ok());
+        for warm_seconds in &[u64::MAX / 2, u64::MAX, i64::MAX as u64, 1_000_000_000_000] {
+            assert!(matches!(
+                sign(*warm_seconds),
+                Err(CertificateError::InvalidValidity)
+            ));
+        }
+
+        let certificate_data = CertificateData {
+            reachability: NodeReachabilityInformation::default(),
+            metadata: NodeMetadata::default(),
+        };
+        let not_before = Utc.ymd(2021, 1, 1).and_hms(12, 0, 0);
+        assert!(matches!(
+            certificate_data.sign_with_validity(
+                private_key.as_ref(),
+                not_before,
+                Duration::seconds(-1)
+            ),
+            Err(CertificateError::InvalidValidity)
+        ));
+        // the validity fits into a Duration, but not_after can not be represented
+        assert!(matches!(
+            certificate_data.sign_with_validity(
+                private_key.as_ref(),
+                not_before,
+                Duration::max_value()
+            ),
+            Err(CertificateError::InvalidValidity)
+        ));
+    }
 }
@@ -1,4 +1,6 @@
+use crate::data::NodeId;
 use std::collections::BTreeSet;
+use std::convert::TryFrom;
 use std::net::IpAddr;
 use yasna::{
     ASN1Error, ASN1ErrorKind, ASN1Result, BERDecodable, BERReader, DEREncodable, DERWriter, Tag,
@@ -13,6 +15,13 @@ pub struct NodeReachabilityInformation {
     pub proxy_reachability: BTreeSet<NodeProxyReachability>,
 }
 
+impl NodeReachabilityInformation {
+    /// Checks if the node can not be reached at all
+    pub fn is_empty(&self) -> bool {
+        self.network_reachability.is_empty() && self.proxy_reachability.is_empty()
+    }
+}
+
 impl DEREncodable for NodeReachabilityInformation {
     fn encode_der(&self, writer: DERWriter) {
         writer.write_sequence(|writer| {
@@ -95,8 +104,8 @@ impl BERDecodable for NodeIpReachability {
 /// Information about how to reach a single node using a proxy node
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct NodeProxyReachability {
-    /// Hash of the public key of the node
-    pub proxy_address: Vec<u8>,
+    /// NodeId of the proxy node
+    pub proxy_address: NodeId,
     /// optional IP-Address information to ommit lookup
     pub proxy_reachability: BTreeSet<NodeIpReachability>,
 }
@@ -104,7 +113,7 @@ pub struct NodeProxyReachability {
 impl DEREncodable for NodeProxyReachability {
     fn encode_der(&self, writer: DERWriter) {
         writer.write_sequence(|writer| {
-            writer.next().write_bytes(self.proxy_address.as_slice());
+            writer.next().write_bytes(self.proxy_address.as_bytes());
             if !self.proxy_reachability.is_empty() {
                 writer.next().write_tagged(Tag::context(0), |writer| {
                     writer.write_set_of(|writer| {
@@ -121,7 +130,8 @@ impl DEREncodable for NodeProxyReachability {
 impl BERDecodable for NodeProxyReachability {
     fn decode_ber(reader: BERReader) -> ASN1Result<Self> {
         reader.read_sequence(|reader| {
-            let proxy_address = reader.next().read_bytes()?;
+            let proxy_address = NodeId::try_from(reader.next().read_bytes()?.as_slice())
+                .map_err(|_err| ASN1Error::new(ASN1ErrorKind::Invalid))?;
             let proxy_reachability = reader
                 .read_optional(|reader| {
                     reader.read_tagged(Tag::context(0), |reader| {
@@ -145,7 +155,9 @@ mod tests {
     use crate::certificate::{
         NodeIpReachability, NodeProxyReachability, NodeReachabilityInformation,
     };
+    use crate::data::NodeId;
     use std::collections::BTreeSet;
+    use yasna::ASN1ErrorKind;
 
     #[test]
     fn test_encode_decode_node_reachability_information() {
@@ -163,12 +175,19 @@ mod tests {
             .into_iter()
             .collect(),
             proxy_reachability: vec![NodeProxyReachability {
-                proxy_address: vec![123, 34, 34, 212, 43, 93],
+                proxy_address: NodeId::from([123; 32]),
                 proxy_reachability: BTreeSet::new(),
             }]
             .into_iter()
             .collect(),
         }];
+
+        for case in testvec {
+            let encoded = yasna::encode_der(&case);
+            let decoded: NodeReachabilityInformation =
+                yasna::decode_der(encoded.as_slice()).unwrap();
+            assert_eq!(case, decoded);
+        }
     }
 
     #[test]
@@ -199,11 +218,11 @@ mod tests {
     fn test_encode_decode_node_proxy_reachability() {
         let testvec = vec![
             NodeProxyReachability {
-                proxy_address: vec![123, 34, 54, 96, 34],
+                proxy_address: NodeId::from([42; 32]),
                 proxy_reachability: BTreeSet::new(),
             },
             NodeProxyReachability {
-                proxy_address: vec![123, 34, 54, 96, 34],
+                proxy_address: NodeId::from([42; 32]),
                 proxy_reachability: vec![NodeIpReachability {
                     address: "2a0e:46c6::2".parse().unwrap(),
                     quic_port: Some(1337),
@@ -219,4 +238,17 @@ mod tests {
             assert_eq!(case, decoded);
         }
     }
+
+    #[test]
+    fn test_decode_node_proxy_reachability_invalid_length() {
+        for length in &[0, 5, 31, 33] {
+            let encoded = yasna::construct_der(|writer| {
+                writer.write_sequence(|writer| {
+                    writer.next().write_bytes(&vec![0; *length]);
+                });
+            });
+            let decoded = yasna::decode_der::<NodeProxyReachability>(encoded.as_slice());
+            assert_eq!(decoded.unwrap_err().kind(), ASN1ErrorKind::Invalid);
+        }
+    }
 }
@@ -0,0 +1,182 @@
+//! Sequence number of signed certificates
+//!
+//! The sequence number of the [`crate::certificate::NodeMetadata`] orders certificates
+//! of a node which were signed in the same second.
+//! It has to increase even if the node is restarted,
+//! so the last used number is written to a file before it is used.
+//!
+//! The `not_before` time of the last signed certificate is stored with it,
+//! so a node restarted with a clock behind does not sign certificates
+//! which are older than the ones it already published.
+
+use crate::file::write_atomically;
+use chrono::{DateTime, TimeZone, Utc};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Monotonically increasing counter for the sequence number of signed certificates
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SequenceCounter {
+    path: Option<PathBuf>,
+    last: u64,
+    not_before: Option<DateTime<Utc>>,
+}
+
+impl Default for SequenceCounter {
+    fn default() -> Self {
+        SequenceCounter::in_memory()
+    }
+}
+
+impl SequenceCounter {
+    /// Counter which is not persisted
+    ///
+    /// Certificates signed after a restart may not be ordered correctly.
+    pub fn in_memory() -> Self {
+        SequenceCounter {
+            path: None,
+            last: 0,
+            not_before: None,
+        }
+    }
+
+    /// Opens a file backed counter
+    ///
+    /// The file contains the last used sequence number as decimal number,
+    /// optionally followed by the `not_before` time of the last signed certificate
+    /// as decimal UNIX timestamp.
+    /// A missing file is treated as a new counter.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+        let mut fields = content.split_whitespace();
+        let last = match fields.next() {
+            Some(last) => last.parse().map_err(invalid)?,
+            None => 0,
+        };
+        let not_before = match fields.next() {
+            Some(timestamp) => {
+                let timestamp = timestamp.parse().map_err(invalid)?;
+                let not_before = Utc.timestamp_opt(timestamp, 0).single().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid not_before time")
+                })?;
+                Some(not_before)
+            }
+            None => None,
+        };
+        if fields.next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected data after the sequence number",
+            ));
+        }
+        Ok(SequenceCounter {
+            path: Some(path),
+            last,
+            not_before,
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Last used sequence number, `0` if none was used yet
+    pub fn last(&self) -> u64 {
+        self.last
+    }
+
+    /// `not_before` time of the last signed certificate, if it was recorded
+    pub fn not_before(&self) -> Option<DateTime<Utc>> {
+        self.not_before
+    }
+
+    /// Increments the counter and returns the new sequence number
+    ///
+    /// The number is persisted before it is returned, see [`write_atomically`].
+    pub fn increment(&mut self) -> io::Result<u64> {
+        self.persist(self.not_before)
+    }
+
+    /// Increments the counter for a certificate starting at `not_before`
+    ///
+    /// The `not_before` time is persisted together with the sequence number,
+    /// unless an even later time was recorded before.
+    pub fn increment_at(&mut self, not_before: DateTime<Utc>) -> io::Result<u64> {
+        self.persist(self.not_before.max(Some(not_before)))
+    }
+
+    fn persist(&mut self, not_before: Option<DateTime<Utc>>) -> io::Result<u64> {
+        let next = self
+            .last
+            .checked_add(1)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "sequence number overflow"))?;
+        if let Some(path) = &self.path {
+            let content = match not_before {
+                Some(not_before) => format!("{} {}\n", next, not_before.timestamp()),
+                None => format!("{}\n", next),
+            };
+            write_atomically(path, content.as_bytes())?;
+        }
+        self.last = next;
+        self.not_before = not_before;
+        Ok(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::SequenceCounter;
+    use chrono::{Duration, TimeZone, Utc};
+    use std::fs;
+
+    #[test]
+    fn test_persist_across_restarts() {
+        let path =
+            std::env::temp_dir().join(format!("globalvpn-sequence-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut counter = SequenceCounter::open(&path).unwrap();
+        assert_eq!(counter.last(), 0);
+        assert_eq!(counter.increment().unwrap(), 1);
+        assert_eq!(counter.increment().unwrap(), 2);
+        drop(counter);
+
+        let mut counter = SequenceCounter::open(&path).unwrap();
+        assert_eq!(counter.last(), 2);
+        assert_eq!(counter.not_before(), None);
+        let not_before = Utc.ymd(2021, 1, 1).and_hms(12, 0, 0);
+        assert_eq!(counter.increment_at(not_before).unwrap(), 3);
+        // an earlier time does not replace the recorded one
+        assert_eq!(
+            counter
+                .increment_at(not_before - Duration::hours(1))
+                .unwrap(),
+            4
+        );
+        drop(counter);
+
+        let mut counter = SequenceCounter::open(&path).unwrap();
+        assert_eq!(counter.last(), 4);
+        assert_eq!(counter.not_before(), Some(not_before));
+        assert_eq!(counter.increment().unwrap(), 5);
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(SequenceCounter::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut counter = SequenceCounter::in_memory();
+        assert_eq!(counter.path(), None);
+        assert_eq!(counter.increment().unwrap(), 1);
+        assert_eq!(counter.increment().unwrap(), 2);
+    }
+}
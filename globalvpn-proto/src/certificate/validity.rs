@@ -0,0 +1,178 @@
+use crate::certificate::{CertificateError, CertificateResult};
+use chrono::{DateTime, Duration, Utc};
+
+/// Default validity of a certificate, if the node metadata does not specify a table time
+pub const DEFAULT_CERTIFICATE_VALIDITY_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+/// Default clock skew which is tolerated when checking the validity of a certificate
+pub const DEFAULT_ALLOWED_CLOCK_SKEW_SECONDS: i64 = 5 * 60;
+
+/// Source of the current time
+///
+/// Used to make time dependent checks testable.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
+
+/// Clock using the system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock which always returns the same point in time
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// Sequence number and validity window of a certificate
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CertificateValidity {
+    /// Sequence number of the node metadata, `0` if it is not set
+    pub sequence_number: u64,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+}
+
+impl CertificateValidity {
+    pub fn version(&self) -> CertificateVersion {
+        CertificateVersion {
+            not_before: self.not_before,
+            sequence_number: self.sequence_number,
+        }
+    }
+
+    /// Checks if the certificate was issued after another one of the same node
+    pub fn is_newer_than(&self, other: &CertificateValidity) -> bool {
+        self.version() > other.version()
+    }
+}
+
+/// Version of a certificate, used to find the newest certificate of a node
+///
+/// Certificates are ordered by `not_before`, the sequence number is used
+/// if both certificates were issued in the same second.
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct CertificateVersion {
+    pub not_before: DateTime<Utc>,
+    pub sequence_number: u64,
+}
+
+/// Rules for checking the validity window of a certificate
+#[derive(Debug, Clone)]
+pub struct ValidityPolicy<C = SystemClock> {
+    /// Clock used to determine the current time
+    pub clock: C,
+    /// Time a certificate is accepted before `not_before` and after `not_after`
+    pub allowed_clock_skew: Duration,
+}
+
+impl Default for ValidityPolicy<SystemClock> {
+    fn default() -> Self {
+        ValidityPolicy::new(SystemClock)
+    }
+}
+
+impl<C: Clock> ValidityPolicy<C> {
+    pub fn new(clock: C) -> Self {
+        ValidityPolicy {
+            clock,
+            allowed_clock_skew: Duration::seconds(DEFAULT_ALLOWED_CLOCK_SKEW_SECONDS),
+        }
+    }
+
+    pub fn with_allowed_clock_skew(mut self, allowed_clock_skew: Duration) -> Self {
+        self.allowed_clock_skew = allowed_clock_skew;
+        self
+    }
+
+    /// Checks if the current time is inside of the validity window
+    ///
+    /// Fails with [`CertificateError::InvalidValidity`] if `not_after` is before `not_before`.
+    pub fn check(
+        &self,
+        not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
+    ) -> CertificateResult<()> {
+        if not_after < not_before {
+            return Err(CertificateError::InvalidValidity);
+        }
+        let now = self.clock.now();
+        if now + self.allowed_clock_skew < not_before {
+            return Err(CertificateError::NotYetValid);
+        }
+        if now - self.allowed_clock_skew > not_after {
+            return Err(CertificateError::Expired);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::validity::{CertificateValidity, FixedClock, ValidityPolicy};
+    use crate::certificate::CertificateError;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_validity_policy() {
+        let not_before = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let not_after = Utc.ymd(2021, 1, 8).and_hms(0, 0, 0);
+        let policy = |now| {
+            ValidityPolicy::new(FixedClock(now)).with_allowed_clock_skew(Duration::minutes(1))
+        };
+
+        assert!(policy(Utc.ymd(2021, 1, 4).and_hms(12, 0, 0))
+            .check(not_before, not_after)
+            .is_ok());
+        assert!(policy(Utc.ymd(2020, 12, 31).and_hms(23, 59, 30))
+            .check(not_before, not_after)
+            .is_ok());
+        assert!(policy(Utc.ymd(2021, 1, 8).and_hms(0, 0, 30))
+            .check(not_before, not_after)
+            .is_ok());
+        assert!(matches!(
+            policy(Utc.ymd(2020, 12, 31).and_hms(23, 58, 0)).check(not_before, not_after),
+            Err(CertificateError::NotYetValid)
+        ));
+        assert!(matches!(
+            policy(Utc.ymd(2021, 1, 8).and_hms(0, 2, 0)).check(not_before, not_after),
+            Err(CertificateError::Expired)
+        ));
+        // an inverted window is rejected, even if the current time lies in between
+        assert!(matches!(
+            policy(Utc.ymd(2021, 1, 4).and_hms(12, 0, 0)).check(not_after, not_before),
+            Err(CertificateError::InvalidValidity)
+        ));
+    }
+
+    #[test]
+    fn test_is_newer_than() {
+        let validity = |day, sequence_number| CertificateValidity {
+            sequence_number,
+            not_before: Utc.ymd(2021, 1, day).and_hms(0, 0, 0),
+            not_after: Utc.ymd(2021, 2, 1).and_hms(0, 0, 0),
+        };
+
+        assert!(validity(2, 1).is_newer_than(&validity(1, 1)));
+        assert!(validity(2, 1).is_newer_than(&validity(1, 5)));
+        assert!(validity(1, 2).is_newer_than(&validity(1, 1)));
+        assert!(!validity(1, 1).is_newer_than(&validity(1, 1)));
+        assert!(!validity(1, 5).is_newer_than(&validity(2, 1)));
+    }
+}
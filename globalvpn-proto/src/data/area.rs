@@ -0,0 +1,118 @@
+//! Area of a node
+//!
+//! Nodes of a regional deployment are tagged with the name of their area,
+//! see [`NodeMetadata::area`](crate::certificate::NodeMetadata::area).
+//! Hybrid dictionary nodes only hold the certificates of their own area,
+//! see [`crate::directory::HybridDictionary`].
+//!
+//! # Textual representation
+//!
+//! An area name consists of 1 to 63 lowercase ASCII letters, digits and hyphens,
+//! like a DNS label. It does not start or end with a hyphen.
+
+use crate::prelude::*;
+use serde::de::Error as _;
+use serde::{Deserializer, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Maximum length of an area name in bytes
+pub const MAX_AREA_LENGTH: usize = 63;
+
+/// Name of an area, e.g. `eu-central`
+#[derive(Clone, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub struct Area {
+    name: String,
+}
+
+impl Area {
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for Area {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl fmt::Debug for Area {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Area({})", self)
+    }
+}
+
+impl FromStr for Area {
+    type Err = AreaParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.len() > MAX_AREA_LENGTH {
+            return Err(AreaParseError::Length);
+        }
+        let valid_character = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+        if !s.chars().all(valid_character) || s.starts_with('-') || s.ends_with('-') {
+            return Err(AreaParseError::Character);
+        }
+        Ok(Area { name: s.to_owned() })
+    }
+}
+
+impl Serialize for Area {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Area {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Error while parsing an [`Area`]
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AreaParseError {
+    #[error("area name has an invalid length")]
+    Length,
+    #[error("area name contains an invalid character")]
+    Character,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::area::{Area, AreaParseError};
+    use crate::prelude::*;
+
+    #[test]
+    fn test_from_str() {
+        let area: Area = "eu-central-1".parse().unwrap();
+        assert_eq!(area.to_string(), "eu-central-1");
+        assert_eq!("a".repeat(63).parse::<Area>().unwrap().as_str().len(), 63);
+
+        assert_eq!("".parse::<Area>(), Err(AreaParseError::Length));
+        assert_eq!("a".repeat(64).parse::<Area>(), Err(AreaParseError::Length));
+        assert_eq!("EU".parse::<Area>(), Err(AreaParseError::Character));
+        assert_eq!("eu central".parse::<Area>(), Err(AreaParseError::Character));
+        assert_eq!("-eu".parse::<Area>(), Err(AreaParseError::Character));
+        assert_eq!("eu-".parse::<Area>(), Err(AreaParseError::Character));
+    }
+
+    #[test]
+    fn test_serde() {
+        #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            area: Area,
+        }
+        let config = Config {
+            area: "eu-central".parse().unwrap(),
+        };
+
+        let toml = toml::to_string(&config).unwrap();
+        assert_eq!(toml, "area = \"eu-central\"\n");
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+        assert!(toml::from_str::<Config>("area = \"EU\"").is_err());
+    }
+}
@@ -1 +1,5 @@
+pub mod area;
 pub mod nodeid;
+
+pub use area::{Area, AreaParseError, MAX_AREA_LENGTH};
+pub use nodeid::{NodeId, NodeIdParseError, NODE_ID_BYTES};
@@ -1,10 +1,231 @@
+//! Node identifier
+//!
+//! # Textual representation
+//!
+//! A NodeId is written as the lowercase base32 (RFC 5155 alphabet, without padding)
+//! encoding of the 32 byte hash followed by a 3 byte checksum.
+//! The checksum are the first 3 bytes of the SHA-256 digest of the hash.
+//! This results in a string of 56 characters.
+//!
+//! Human readable serde formats (e.g. TOML) use the textual representation,
+//! binary formats (e.g. MessagePack) use the raw 32 bytes.
+
 use crate::prelude::*;
+use data_encoding::BASE32_DNSSEC;
+use ring::digest::{digest, SHA256};
+use serde::de::{Error as _, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Length of a NodeId in bytes
+pub const NODE_ID_BYTES: usize = sodiumoxide::crypto::hash::sha256::DIGESTBYTES;
+
+/// Length of the checksum in the textual representation in bytes
+const NODE_ID_CHECKSUM_BYTES: usize = 3;
 
 /// Hash value of the public signing key of a node
 ///
 /// The NodeId is used to identify a node in the Node directionary
-#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
-#[serde(transparent)]
+#[derive(Clone, Copy, Hash, Ord, PartialOrd, Eq, PartialEq)]
 pub struct NodeId {
-    hash: [u8; sodiumoxide::crypto::hash::sha256::DIGESTBYTES],
+    hash: [u8; NODE_ID_BYTES],
+}
+
+impl NodeId {
+    /// Creates the NodeId of a node using the SHA-256 digest of its raw public signing key
+    pub fn from_public_key(public_key: &[u8]) -> NodeId {
+        let mut hash = [0u8; NODE_ID_BYTES];
+        hash.copy_from_slice(digest(&SHA256, public_key).as_ref());
+        NodeId { hash }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; NODE_ID_BYTES] {
+        &self.hash
+    }
+}
+
+impl From<[u8; NODE_ID_BYTES]> for NodeId {
+    fn from(hash: [u8; NODE_ID_BYTES]) -> Self {
+        NodeId { hash }
+    }
+}
+
+impl TryFrom<&[u8]> for NodeId {
+    type Error = NodeIdParseError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != NODE_ID_BYTES {
+            return Err(NodeIdParseError::Length);
+        }
+        let mut hash = [0u8; NODE_ID_BYTES];
+        hash.copy_from_slice(value);
+        Ok(NodeId { hash })
+    }
+}
+
+fn checksum(hash: &[u8]) -> [u8; NODE_ID_CHECKSUM_BYTES] {
+    let mut checksum = [0u8; NODE_ID_CHECKSUM_BYTES];
+    checksum.copy_from_slice(&digest(&SHA256, hash).as_ref()[..NODE_ID_CHECKSUM_BYTES]);
+    checksum
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut data = [0u8; NODE_ID_BYTES + NODE_ID_CHECKSUM_BYTES];
+        data[..NODE_ID_BYTES].copy_from_slice(&self.hash);
+        data[NODE_ID_BYTES..].copy_from_slice(&checksum(&self.hash));
+        f.write_str(&BASE32_DNSSEC.encode(&data))
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeId({})", self)
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = NodeIdParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = BASE32_DNSSEC
+            .decode(s.as_bytes())
+            .map_err(|_err| NodeIdParseError::Encoding)?;
+        if data.len() != NODE_ID_BYTES + NODE_ID_CHECKSUM_BYTES {
+            return Err(NodeIdParseError::Length);
+        }
+        let (hash, expected_checksum) = data.split_at(NODE_ID_BYTES);
+        if checksum(hash) != expected_checksum {
+            return Err(NodeIdParseError::Checksum);
+        }
+        NodeId::try_from(hash)
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.hash)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(NodeIdVisitor)
+        } else {
+            deserializer.deserialize_bytes(NodeIdVisitor)
+        }
+    }
+}
+
+struct NodeIdVisitor;
+
+impl<'de> Visitor<'de> for NodeIdVisitor {
+    type Value = NodeId;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a NodeId as string or {} bytes", NODE_ID_BYTES)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        NodeId::try_from(v).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut hash = [0u8; NODE_ID_BYTES];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(A::Error::invalid_length(NODE_ID_BYTES + 1, &self));
+        }
+        Ok(NodeId { hash })
+    }
+}
+
+/// Error while parsing a [`NodeId`]
+#[derive(Error, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NodeIdParseError {
+    #[error("NodeId is not valid base32")]
+    Encoding,
+    #[error("NodeId has an invalid length")]
+    Length,
+    #[error("NodeId has an invalid checksum")]
+    Checksum,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::nodeid::{NodeId, NodeIdParseError};
+    use crate::prelude::*;
+
+    #[test]
+    fn test_from_public_key() {
+        // SHA-256 of the empty string
+        let expected = [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+            0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+            0x78, 0x52, 0xb8, 0x55,
+        ];
+        assert_eq!(NodeId::from_public_key(&[]), NodeId::from(expected));
+        assert_ne!(
+            NodeId::from_public_key(&[1; 32]),
+            NodeId::from_public_key(&[2; 32])
+        );
+    }
+
+    #[test]
+    fn test_display_from_str() {
+        let node_id = NodeId::from_public_key(b"test");
+        let encoded = node_id.to_string();
+        assert_eq!(encoded.len(), 56);
+        assert_eq!(encoded, encoded.to_lowercase());
+        assert_eq!(encoded.parse::<NodeId>().unwrap(), node_id);
+        assert_eq!(encoded.to_uppercase().parse::<NodeId>().unwrap(), node_id);
+
+        let mut corrupted = encoded.into_bytes();
+        corrupted[0] = if corrupted[0] == b'0' { b'1' } else { b'0' };
+        assert_eq!(
+            String::from_utf8(corrupted).unwrap().parse::<NodeId>(),
+            Err(NodeIdParseError::Checksum)
+        );
+        assert_eq!("01234567".parse::<NodeId>(), Err(NodeIdParseError::Length));
+        assert_eq!(
+            "not base32!".parse::<NodeId>(),
+            Err(NodeIdParseError::Encoding)
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            node_id: NodeId,
+        }
+        let config = Config {
+            node_id: NodeId::from_public_key(b"test"),
+        };
+
+        let toml = toml::to_string(&config).unwrap();
+        assert_eq!(toml, format!("node_id = \"{}\"\n", config.node_id));
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+
+        let msgpack = rmp_serde::to_vec(&config).unwrap();
+        // fixarray(1), bin8 marker, length, 32 bytes
+        assert_eq!(msgpack.len(), 3 + 32);
+        assert_eq!(rmp_serde::from_slice::<Config>(&msgpack).unwrap(), config);
+    }
 }
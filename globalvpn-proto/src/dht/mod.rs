@@ -0,0 +1,22 @@
+//! Distributed dictionary
+//!
+//! Dictionary nodes which can not hold the global table store only a part of it.
+//! The XOR distance of two [`NodeId`](crate::data::NodeId)s decides which
+//! dictionary nodes are responsible for a certificate:
+//! it is stored on the `k` dictionary nodes closest to the NodeId of the certificate.
+//!
+//! Dictionary nodes find each other with iterative FIND_NODE and FIND_VALUE lookups,
+//! see [`crate::protocol::dht`]. A node publishes its certificate again before
+//! its warm table time runs out, see [`DhtNode::republish_periodically`].
+
+pub mod node;
+pub mod routing;
+
+pub use node::{DhtConfig, DhtNode, DhtTransport};
+pub use routing::{Contact, Distance, RoutingTable};
+
+/// Default size of a bucket and number of nodes storing each certificate
+pub const DEFAULT_K: usize = 20;
+
+/// Default number of requests sent in parallel during a lookup
+pub const DEFAULT_ALPHA: usize = 3;
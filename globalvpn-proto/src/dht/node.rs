@@ -0,0 +1,706 @@
+//! Dictionary node taking part in the distributed dictionary
+//!
+//! The node answers FIND_NODE and FIND_VALUE requests from its routing table and store,
+//! see [`DhtNode::handle`], and performs iterative lookups using a [`DhtTransport`].
+//!
+//! An iterative lookup starts with the closest known contacts and asks up to `alpha`
+//! of them in parallel for contacts even closer to the target.
+//! It ends when the `k` closest contacts found so far have all been asked.
+//! Contacts returned by other nodes are only added to the routing table
+//! once they answered a request themselves, so a malicious node can not fill
+//! the routing table with contacts it controls or made up.
+//! Contacts which do not answer or answer with something else than requested
+//! are removed from the routing table.
+//!
+//! A joining node looks up its own NodeId and then a random NodeId in every bucket
+//! farther away than its closest contact, so it knows contacts in all parts of the network.
+
+use crate::certificate::{CertificateResult, Clock, RawCertificate, SystemClock};
+use crate::data::{NodeId, NODE_ID_BYTES};
+use crate::dht::routing::{Contact, Distance, RoutingTable, BUCKET_COUNT};
+use crate::dht::{DEFAULT_ALPHA, DEFAULT_K};
+use crate::directory::store::{Store, StoreEntry};
+use crate::protocol::dht::{FindPacket, NodesPacket};
+use crate::protocol::error::ProtocolResult;
+use crate::protocol::frame::Frame;
+use crate::protocol::lookup::LookupResponsePacket;
+use crate::protocol::update::UpdatePacket;
+use futures::future::{join_all, BoxFuture};
+use log::debug;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+/// Parameters of the distributed dictionary
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DhtConfig {
+    /// Size of a bucket and number of nodes storing each certificate
+    pub k: usize,
+    /// Number of requests sent in parallel during a lookup
+    pub alpha: usize,
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        DhtConfig {
+            k: DEFAULT_K,
+            alpha: DEFAULT_ALPHA,
+        }
+    }
+}
+
+/// Sends frames to other dictionary nodes
+pub trait DhtTransport: Send + Sync {
+    /// Sends a FIND_NODE or FIND_VALUE request and waits for the answer
+    fn request<'a>(
+        &'a self,
+        contact: &'a Contact,
+        frame: Frame,
+    ) -> BoxFuture<'a, ProtocolResult<Frame>>;
+
+    /// Sends a frame which is not answered, e.g. an UPDATE
+    fn send<'a>(&'a self, contact: &'a Contact, frame: Frame) -> BoxFuture<'a, ProtocolResult<()>>;
+}
+
+/// Dictionary node holding the certificates it is responsible for
+#[derive(Debug)]
+pub struct DhtNode<C = SystemClock> {
+    contact: Contact,
+    config: DhtConfig,
+    routing: Mutex<RoutingTable>,
+    store: Mutex<Store<C>>,
+    next_request_id: AtomicU32,
+    rng: SystemRandom,
+}
+
+impl<C: Clock> DhtNode<C> {
+    /// Creates a node from its own certificate
+    ///
+    /// `store` holds the certificates this node is responsible for.
+    pub fn new(
+        certificate: RawCertificate,
+        store: Store<C>,
+        config: DhtConfig,
+    ) -> CertificateResult<Self> {
+        let (node_id, _entry) = StoreEntry::decode(certificate.clone(), store.policy())?;
+        Ok(DhtNode {
+            contact: Contact {
+                node_id,
+                certificate,
+            },
+            config,
+            routing: Mutex::new(RoutingTable::new(node_id, config.k)),
+            store: Mutex::new(store),
+            next_request_id: AtomicU32::new(0),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.contact.node_id
+    }
+
+    pub fn contact(&self) -> &Contact {
+        &self.contact
+    }
+
+    pub fn config(&self) -> DhtConfig {
+        self.config
+    }
+
+    /// Number of contacts in the routing table
+    pub fn contacts(&self) -> usize {
+        self.routing.lock().unwrap().len()
+    }
+
+    /// Stored certificate of a node, if its warm table time is not over
+    pub fn get(&self, node_id: &NodeId) -> Option<StoreEntry> {
+        self.store.lock().unwrap().get(node_id).cloned()
+    }
+
+    /// Answers a request of another dictionary node
+    ///
+    /// UPDATE packets store the certificate and are not answered.
+    /// Returns `None` for frames which do not belong to the distributed dictionary.
+    pub fn handle(&self, frame: Frame) -> Option<Frame> {
+        match frame {
+            Frame::FindNode(packet) => {
+                let requester = packet
+                    .sender
+                    .and_then(|sender| self.learn_requester(sender));
+                Some(self.nodes(packet.request_id, &packet.target, requester))
+            }
+            Frame::FindValue(packet) => {
+                let requester = packet
+                    .sender
+                    .and_then(|sender| self.learn_requester(sender));
+                match self.get(&packet.target) {
+                    Some(entry) => Some(Frame::LookupResponse(LookupResponsePacket {
+                        request_id: packet.request_id,
+                        certificate: entry.certificate,
+                    })),
+                    None => Some(self.nodes(packet.request_id, &packet.target, requester)),
+                }
+            }
+            Frame::Update(packet) => {
+                if let Err(err) = self.store.lock().unwrap().insert(packet.certificate) {
+                    debug!("not storing certificate: {}", err);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn nodes(&self, request_id: u32, target: &NodeId, requester: Option<NodeId>) -> Frame {
+        let contacts = self
+            .routing
+            .lock()
+            .unwrap()
+            .closest(target, self.config.k + 1)
+            .into_iter()
+            .filter(|contact| Some(contact.node_id) != requester)
+            .take(self.config.k)
+            .map(|contact| contact.certificate);
+        Frame::Nodes(NodesPacket::new(request_id, contacts))
+    }
+
+    /// Adds the sender of a request to the routing table, if there is room for it
+    ///
+    /// Returns the NodeId of the sender, which is left out of the answer.
+    fn learn_requester(&self, certificate: RawCertificate) -> Option<NodeId> {
+        let node_id = certificate.node_id().ok()?;
+        if self.routing.lock().unwrap().has_room(&node_id) {
+            self.learn(certificate);
+        }
+        Some(node_id)
+    }
+
+    /// Verifies the certificate of a dictionary node and adds it to the routing table
+    ///
+    /// Only used for nodes which contacted this node directly or were configured,
+    /// contacts returned by other nodes are added once they answered a request.
+    /// Returns the NodeId of the contact, see [`DhtNode::verify`].
+    fn learn(&self, certificate: RawCertificate) -> Option<NodeId> {
+        let contact = self.verify(certificate)?;
+        let node_id = contact.node_id;
+        self.routing.lock().unwrap().insert(contact);
+        Some(node_id)
+    }
+
+    /// Verifies the certificate of a dictionary node
+    ///
+    /// Returns the contact, unless the certificate is invalid,
+    /// a tombstone or the certificate of the local node.
+    /// A tombstone removes the node from the routing table.
+    fn verify(&self, certificate: RawCertificate) -> Option<Contact> {
+        let node_id = certificate.node_id().ok()?;
+        if node_id == self.contact.node_id {
+            return None;
+        }
+        let mut routing = self.routing.lock().unwrap();
+        let known = routing
+            .get(&node_id)
+            .is_some_and(|contact| contact.certificate == certificate);
+        if !known {
+            let store = self.store.lock().unwrap();
+            match StoreEntry::decode(certificate.clone(), store.policy()) {
+                Ok((_, entry)) if !entry.is_tombstone() => {}
+                Ok(_tombstone) => {
+                    routing.remove(&node_id);
+                    return None;
+                }
+                Err(err) => {
+                    debug!("ignoring contact {}: {}", node_id, err);
+                    return None;
+                }
+            }
+        }
+        Some(Contact {
+            node_id,
+            certificate,
+        })
+    }
+
+    /// Joins the distributed dictionary using the certificates of known dictionary nodes
+    ///
+    /// Returns the number of contacts in the routing table afterwards.
+    pub async fn join(
+        &self,
+        transport: &dyn DhtTransport,
+        bootstrap: impl IntoIterator<Item = RawCertificate>,
+    ) -> usize {
+        for certificate in bootstrap {
+            self.learn(certificate);
+        }
+        self.find_node(transport, self.contact.node_id).await;
+
+        // the lookup of the own NodeId only finds close contacts,
+        // so the buckets of contacts farther away are filled separately
+        let closest_bucket = self.routing.lock().unwrap().closest_bucket();
+        if let Some(closest_bucket) = closest_bucket {
+            for index in closest_bucket + 1..BUCKET_COUNT {
+                let mut random = [0; NODE_ID_BYTES];
+                self.rng
+                    .fill(&mut random)
+                    .expect("system random number generator failed");
+                let target = self.routing.lock().unwrap().id_in_bucket(index, random);
+                self.find_node(transport, target).await;
+            }
+        }
+        self.contacts()
+    }
+
+    /// Finds the `k` dictionary nodes closest to `target`
+    pub async fn find_node(&self, transport: &dyn DhtTransport, target: NodeId) -> Vec<Contact> {
+        self.iterate(transport, target, false).await.0
+    }
+
+    /// Finds the certificate of a node
+    pub async fn find_value(
+        &self,
+        transport: &dyn DhtTransport,
+        target: NodeId,
+    ) -> Option<StoreEntry> {
+        if let Some(entry) = self.get(&target) {
+            return Some(entry);
+        }
+        self.iterate(transport, target, true).await.1
+    }
+
+    /// Stores a certificate on the `k` dictionary nodes closest to its NodeId
+    ///
+    /// The certificate is stored locally as well, if this node is one of them.
+    /// Returns the number of nodes the certificate was sent to.
+    pub async fn publish(
+        &self,
+        transport: &dyn DhtTransport,
+        certificate: RawCertificate,
+    ) -> CertificateResult<usize> {
+        let node_id = {
+            let store = self.store.lock().unwrap();
+            StoreEntry::decode(certificate.clone(), store.policy())?.0
+        };
+        let closest = self.find_node(transport, node_id).await;
+        let own_distance = Distance::between(&self.contact.node_id, &node_id);
+        let responsible = closest.len() < self.config.k
+            || closest.last().map_or(true, |farthest| {
+                own_distance < Distance::between(&farthest.node_id, &node_id)
+            });
+        if responsible {
+            self.store.lock().unwrap().insert(certificate.clone())?;
+        }
+
+        let frame = Frame::Update(UpdatePacket::new(certificate));
+        let sent = join_all(
+            closest
+                .iter()
+                .map(|contact| transport.send(contact, frame.clone())),
+        )
+        .await;
+        Ok(sent.into_iter().filter(Result::is_ok).count())
+    }
+
+    /// Iterative lookup of the contacts closest to `target`
+    ///
+    /// If `find_value` is set, the lookup ends as soon as a contact returns
+    /// a valid certificate of `target`.
+    async fn iterate(
+        &self,
+        transport: &dyn DhtTransport,
+        target: NodeId,
+        find_value: bool,
+    ) -> (Vec<Contact>, Option<StoreEntry>) {
+        let k = self.config.k;
+        let mut shortlist = self.routing.lock().unwrap().closest(&target, k);
+        let mut queried = HashSet::new();
+        let mut unreachable = HashSet::new();
+
+        loop {
+            let batch: Vec<Contact> = shortlist
+                .iter()
+                .take(k)
+                .filter(|contact| !queried.contains(&contact.node_id))
+                .take(self.config.alpha)
+                .cloned()
+                .collect();
+            if batch.is_empty() {
+                break;
+            }
+
+            let requests = batch.iter().map(|contact| {
+                queried.insert(contact.node_id);
+                let packet = FindPacket {
+                    request_id: self.next_request_id.fetch_add(1, Ordering::Relaxed),
+                    target,
+                    sender: Some(self.contact.certificate.clone()),
+                };
+                let frame = if find_value {
+                    Frame::FindValue(packet)
+                } else {
+                    Frame::FindNode(packet)
+                };
+                transport.request(contact, frame)
+            });
+            let answers = join_all(requests.collect::<Vec<_>>()).await;
+
+            for (contact, answer) in batch.into_iter().zip(answers) {
+                let node_id = contact.node_id;
+                match answer {
+                    Ok(Frame::Nodes(packet)) => {
+                        self.routing.lock().unwrap().insert(contact);
+                        for certificate in packet.contacts {
+                            // other nodes may not have noticed an unreachable contact yet
+                            let node_id = match certificate.node_id() {
+                                Ok(node_id) if !unreachable.contains(&node_id) => node_id,
+                                _ => continue,
+                            };
+                            if shortlist.iter().any(|known| known.node_id == node_id) {
+                                continue;
+                            }
+                            // added to the routing table only once it answered itself
+                            if let Some(contact) = self.verify(certificate) {
+                                shortlist.push(contact);
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(Frame::LookupResponse(packet)) if find_value => {
+                        let value = {
+                            let store = self.store.lock().unwrap();
+                            StoreEntry::decode(packet.certificate, store.policy())
+                        };
+                        match value {
+                            Ok((value_id, entry)) if value_id == target => {
+                                self.routing.lock().unwrap().insert(contact);
+                                return (shortlist, Some(entry));
+                            }
+                            Ok((value_id, _)) => {
+                                debug!("{} returned the certificate of {}", node_id, value_id)
+                            }
+                            Err(err) => debug!("{} returned invalid value: {}", node_id, err),
+                        }
+                    }
+                    Ok(other) => debug!("unexpected answer from {}: {:?}", node_id, other),
+                    Err(err) => debug!("contact {} is unreachable: {}", node_id, err),
+                }
+                // contacts which did not answer as requested are not asked again
+                debug!("removing contact {}", node_id);
+                self.routing.lock().unwrap().remove(&node_id);
+                unreachable.insert(node_id);
+                shortlist.retain(|known| known.node_id != node_id);
+            }
+            shortlist.sort_by_key(|contact| Distance::between(&contact.node_id, &target));
+        }
+
+        shortlist.retain(|contact| queried.contains(&contact.node_id));
+        shortlist.truncate(k);
+        (shortlist, None)
+    }
+
+    /// Publishes the certificate returned by `sign` again
+    /// before its warm table time runs out
+    ///
+    /// The certificate is signed and published immediately and then every time
+    /// half of its warm table time is over.
+    /// Only returns if signing or publishing failed.
+    pub async fn republish_periodically<F>(
+        &self,
+        transport: &dyn DhtTransport,
+        mut sign: F,
+    ) -> CertificateResult<()>
+    where
+        F: FnMut() -> CertificateResult<RawCertificate>,
+    {
+        loop {
+            let certificate = sign()?;
+            let entry = {
+                let store = self.store.lock().unwrap();
+                StoreEntry::decode(certificate.clone(), store.policy())?.1
+            };
+            self.publish(transport, certificate).await?;
+            let interval = (entry.expires_at() - entry.validity.not_before) / 2;
+            let interval = interval.to_std().unwrap_or_default();
+            tokio::time::sleep(interval.max(std::time::Duration::from_secs(1))).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{CertificateResult, NodeMetadata, RawCertificate};
+    use crate::data::NodeId;
+    use crate::dht::node::{DhtConfig, DhtNode, DhtTransport};
+    use crate::dht::routing::{Contact, Distance};
+    use crate::directory::store::Store;
+    use crate::protocol::dht::NodesPacket;
+    use crate::protocol::error::{ProtocolError, ProtocolResult};
+    use crate::protocol::frame::{Frame, FrameCodec};
+    use crate::protocol::lookup::LookupResponsePacket;
+    use crate::test_util::{self, private_key};
+    use bytes::BytesMut;
+    use chrono::Utc;
+    use futures::future::BoxFuture;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    /// Dictionary nodes connected by an in-memory transport
+    ///
+    /// Every frame is encoded and decoded, as if it was sent over a connection.
+    #[derive(Default)]
+    struct MemoryNetwork {
+        nodes: HashMap<NodeId, Arc<DhtNode>>,
+        offline: Mutex<HashSet<NodeId>>,
+        requests: AtomicUsize,
+    }
+
+    impl MemoryNetwork {
+        /// Creates `size` dictionary nodes, each joining using the first node
+        async fn new(size: usize, config: DhtConfig) -> Self {
+            let mut network = MemoryNetwork::default();
+            let mut bootstrap = None;
+            for _ in 0..size {
+                let node = Arc::new(
+                    DhtNode::new(sign(&private_key(), 3600, 0), Store::default(), config).unwrap(),
+                );
+                network.nodes.insert(node.node_id(), node.clone());
+                let first = bootstrap.get_or_insert_with(|| node.contact().certificate.clone());
+                node.join(&network, vec![first.clone()]).await;
+            }
+            network
+        }
+
+        fn node(&self, index: usize) -> &Arc<DhtNode> {
+            let mut node_ids: Vec<&NodeId> = self.nodes.keys().collect();
+            node_ids.sort();
+            &self.nodes[node_ids[index % node_ids.len()]]
+        }
+
+        /// NodeIds of the `count` nodes which are online and closest to `target`
+        fn closest(&self, target: &NodeId, count: usize) -> Vec<NodeId> {
+            let offline = self.offline.lock().unwrap();
+            let mut node_ids: Vec<NodeId> = self
+                .nodes
+                .keys()
+                .filter(|node_id| !offline.contains(node_id))
+                .copied()
+                .collect();
+            node_ids.sort_by_key(|node_id| Distance::between(node_id, target));
+            node_ids.truncate(count);
+            node_ids
+        }
+
+        fn deliver(&self, contact: &Contact, frame: Frame) -> ProtocolResult<Option<Frame>> {
+            if self.offline.lock().unwrap().contains(&contact.node_id) {
+                return Err(ProtocolError::ConnectionClosed);
+            }
+            let node = self
+                .nodes
+                .get(&contact.node_id)
+                .ok_or(ProtocolError::ConnectionClosed)?;
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            node.handle(roundtrip(frame)?).map(roundtrip).transpose()
+        }
+    }
+
+    impl DhtTransport for MemoryNetwork {
+        fn request<'a>(
+            &'a self,
+            contact: &'a Contact,
+            frame: Frame,
+        ) -> BoxFuture<'a, ProtocolResult<Frame>> {
+            Box::pin(async move {
+                self.deliver(contact, frame)?
+                    .ok_or(ProtocolError::ConnectionClosed)
+            })
+        }
+
+        fn send<'a>(
+            &'a self,
+            contact: &'a Contact,
+            frame: Frame,
+        ) -> BoxFuture<'a, ProtocolResult<()>> {
+            Box::pin(async move { self.deliver(contact, frame).map(|_answer| ()) })
+        }
+    }
+
+    fn roundtrip(frame: Frame) -> ProtocolResult<Frame> {
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(frame, &mut buf)?;
+        codec.decode(&mut buf)?.ok_or(ProtocolError::UnexpectedEof)
+    }
+
+    fn sign(private_key: &[u8], warm_seconds: u64, sequence_number: u64) -> RawCertificate {
+        let metadata = NodeMetadata {
+            maximum_warm_table_seconds: Some(warm_seconds),
+            sequence_number: Some(sequence_number),
+            ..NodeMetadata::default()
+        };
+        test_util::sign(private_key, metadata, Utc::now())
+    }
+
+    fn config() -> DhtConfig {
+        DhtConfig { k: 8, alpha: 3 }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_in_large_network() {
+        let network = MemoryNetwork::new(256, config()).await;
+        for index in 0..256 {
+            assert!(network.node(index).contacts() >= config().k);
+        }
+
+        let certificates: Vec<RawCertificate> =
+            (0..32).map(|_| sign(&private_key(), 3600, 0)).collect();
+        for (index, certificate) in certificates.iter().enumerate() {
+            let publisher = network.node(index * 7);
+            let sent = publisher
+                .publish(&network, certificate.clone())
+                .await
+                .unwrap();
+            assert_eq!(sent, config().k);
+        }
+
+        for (index, certificate) in certificates.iter().enumerate() {
+            let node_id = certificate.node_id().unwrap();
+            // the certificate is stored on the k closest nodes
+            let stored = network
+                .closest(&node_id, config().k)
+                .iter()
+                .filter(|closest| network.nodes[closest].get(&node_id).is_some())
+                .count();
+            assert_eq!(stored, config().k);
+
+            let requests = network.requests.load(Ordering::Relaxed);
+            let entry = network
+                .node(index * 13 + 5)
+                .find_value(&network, node_id)
+                .await
+                .unwrap();
+            assert_eq!(&entry.certificate, certificate);
+            // a lookup only asks a small part of the network
+            assert!(network.requests.load(Ordering::Relaxed) - requests < 64);
+        }
+        assert!(network
+            .node(0)
+            .find_value(&network, NodeId::from([0x42; 32]))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_with_offline_nodes() {
+        let network = MemoryNetwork::new(64, config()).await;
+        let certificate = sign(&private_key(), 3600, 0);
+        let node_id = certificate.node_id().unwrap();
+        network
+            .node(3)
+            .publish(&network, certificate.clone())
+            .await
+            .unwrap();
+
+        // half of the nodes storing the certificate fail
+        let closest = network.closest(&node_id, config().k);
+        network
+            .offline
+            .lock()
+            .unwrap()
+            .extend(closest.iter().take(config().k / 2));
+
+        let searcher = network
+            .nodes
+            .values()
+            .find(|node| !closest.contains(&node.node_id()))
+            .unwrap();
+        let entry = searcher.find_value(&network, node_id).await.unwrap();
+        assert_eq!(entry.certificate, certificate);
+        let contacts = searcher.find_node(&network, node_id).await;
+        assert_eq!(contacts.len(), config().k);
+        for contact in contacts {
+            assert!(!network.offline.lock().unwrap().contains(&contact.node_id));
+        }
+    }
+
+    /// The honest contact answers FIND_NODE with a NODES packet containing made up contacts,
+    /// which answer requests with keepalives, and FIND_VALUE with a wrong certificate
+    struct EclipseTransport {
+        honest: NodeId,
+        made_up: Vec<RawCertificate>,
+    }
+
+    impl DhtTransport for EclipseTransport {
+        fn request<'a>(
+            &'a self,
+            contact: &'a Contact,
+            frame: Frame,
+        ) -> BoxFuture<'a, ProtocolResult<Frame>> {
+            Box::pin(async move {
+                match frame {
+                    Frame::FindNode(packet) if contact.node_id == self.honest => Ok(Frame::Nodes(
+                        NodesPacket::new(packet.request_id, self.made_up.clone()),
+                    )),
+                    Frame::FindValue(packet) if contact.node_id == self.honest => {
+                        Ok(Frame::LookupResponse(LookupResponsePacket {
+                            request_id: packet.request_id,
+                            certificate: self.made_up[0].clone(),
+                        }))
+                    }
+                    _ => Ok(Frame::Keepalive),
+                }
+            })
+        }
+
+        fn send<'a>(&'a self, _: &'a Contact, _: Frame) -> BoxFuture<'a, ProtocolResult<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_misbehaving_contacts() {
+        let node = DhtNode::new(sign(&private_key(), 3600, 0), Store::default(), config()).unwrap();
+        let honest = sign(&private_key(), 3600, 0);
+        let transport = EclipseTransport {
+            honest: honest.node_id().unwrap(),
+            made_up: (0..config().k)
+                .map(|_| sign(&private_key(), 3600, 0))
+                .collect(),
+        };
+        node.join(&transport, vec![honest]).await;
+        assert_eq!(node.contacts(), 1);
+        assert!(node
+            .find_node(&transport, NodeId::from([0x42; 32]))
+            .await
+            .iter()
+            .all(|contact| contact.node_id == transport.honest));
+
+        // a wrong answer removes the contact from the routing table
+        let target = NodeId::from([0x42; 32]);
+        assert!(node.find_value(&transport, target).await.is_none());
+        assert_eq!(node.contacts(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_republish_before_warm_table_time() {
+        let network = MemoryNetwork::new(16, config()).await;
+        let key = private_key();
+        let node_id = sign(&key, 60, 0).node_id().unwrap();
+        let mut sequence_number = 0;
+        let sign = || -> CertificateResult<RawCertificate> {
+            sequence_number += 1;
+            Ok(sign(&key, 60, sequence_number))
+        };
+
+        let republish = network.node(0).republish_periodically(&network, sign);
+        let result = tokio::time::timeout(std::time::Duration::from_secs(100), republish).await;
+        assert!(result.is_err());
+
+        // published at 0, 30, 60 and 90 seconds
+        let closest = network.closest(&node_id, 1)[0];
+        let entry = network.nodes[&closest].get(&node_id).unwrap();
+        assert_eq!(entry.validity.sequence_number, 4);
+    }
+}
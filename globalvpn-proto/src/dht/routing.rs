@@ -0,0 +1,268 @@
+//! Routing table of k-buckets
+//!
+//! Contacts are sorted into buckets by the XOR distance of their [`NodeId`]
+//! to the NodeId of the local node.
+//! Bucket `i` holds the contacts with a distance in `[2^i, 2^(i+1))`,
+//! so the local node knows many contacts close to itself and few far away.
+//!
+//! Each bucket holds at most `k` contacts, ordered from least to most recently seen.
+//! If a bucket is full, new contacts are dropped, as long known contacts
+//! are likely to stay reachable. Unreachable contacts are removed by the caller.
+
+use crate::certificate::RawCertificate;
+use crate::data::{NodeId, NODE_ID_BYTES};
+use std::collections::VecDeque;
+
+/// Number of buckets, one for every bit of a [`NodeId`]
+pub const BUCKET_COUNT: usize = NODE_ID_BYTES * 8;
+
+/// XOR distance of two [`NodeId`]s
+///
+/// Distances are ordered as 256 bit big endian integers.
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Distance([u8; NODE_ID_BYTES]);
+
+impl Distance {
+    pub fn between(a: &NodeId, b: &NodeId) -> Self {
+        let mut distance = [0; NODE_ID_BYTES];
+        for (distance, (a, b)) in distance
+            .iter_mut()
+            .zip(a.as_bytes().iter().zip(b.as_bytes()))
+        {
+            *distance = a ^ b;
+        }
+        Distance(distance)
+    }
+
+    pub fn leading_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for byte in &self.0 {
+            zeros += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+}
+
+/// Dictionary node taking part in the distributed dictionary
+///
+/// The certificate was verified before the contact was created.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Contact {
+    pub node_id: NodeId,
+    pub certificate: RawCertificate,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    own_id: NodeId,
+    k: usize,
+    buckets: Vec<VecDeque<Contact>>,
+}
+
+impl RoutingTable {
+    pub fn new(own_id: NodeId, k: usize) -> Self {
+        RoutingTable {
+            own_id,
+            k,
+            buckets: vec![VecDeque::new(); BUCKET_COUNT],
+        }
+    }
+
+    pub fn own_id(&self) -> &NodeId {
+        &self.own_id
+    }
+
+    /// Index of the bucket a node belongs to, `None` for the local node
+    pub fn bucket_index(&self, node_id: &NodeId) -> Option<usize> {
+        let zeros = Distance::between(&self.own_id, node_id).leading_zeros() as usize;
+        BUCKET_COUNT.checked_sub(zeros + 1)
+    }
+
+    /// Adds a contact or marks it as most recently seen
+    ///
+    /// Returns `false` if the bucket of the contact is full
+    /// or the contact is the local node.
+    pub fn insert(&mut self, contact: Contact) -> bool {
+        let k = self.k;
+        let bucket = match self.bucket_index(&contact.node_id) {
+            Some(index) => &mut self.buckets[index],
+            None => return false,
+        };
+        if let Some(position) = bucket
+            .iter()
+            .position(|known| known.node_id == contact.node_id)
+        {
+            bucket.remove(position);
+        } else if bucket.len() >= k {
+            return false;
+        }
+        bucket.push_back(contact);
+        true
+    }
+
+    /// Checks if a contact is known or its bucket is not full
+    pub fn has_room(&self, node_id: &NodeId) -> bool {
+        match self.bucket_index(node_id) {
+            Some(index) => {
+                let bucket = &self.buckets[index];
+                bucket.len() < self.k || bucket.iter().any(|known| known.node_id == *node_id)
+            }
+            None => false,
+        }
+    }
+
+    /// Index of the first non-empty bucket, containing the closest contacts
+    pub fn closest_bucket(&self) -> Option<usize> {
+        self.buckets.iter().position(|bucket| !bucket.is_empty())
+    }
+
+    /// NodeId belonging to the bucket `index`, using `random` for the lower bits
+    ///
+    /// Used to fill a bucket by looking up a NodeId in its range.
+    pub fn id_in_bucket(&self, index: usize, random: [u8; NODE_ID_BYTES]) -> NodeId {
+        let mut id = [0; NODE_ID_BYTES];
+        for (position, (id, (own, random))) in id
+            .iter_mut()
+            .zip(self.own_id.as_bytes().iter().zip(random.iter()))
+            .enumerate()
+        {
+            // bits of the distance, counted from the most significant bit of the byte
+            let first_bit = BUCKET_COUNT - 8 * (position + 1);
+            let distance = if index < first_bit {
+                0
+            } else if index >= first_bit + 8 {
+                *random
+            } else {
+                let bit = index - first_bit;
+                let mask = (1u16 << bit) as u8;
+                (random & (mask.wrapping_sub(1))) | mask
+            };
+            *id = own ^ distance;
+        }
+        NodeId::from(id)
+    }
+
+    pub fn remove(&mut self, node_id: &NodeId) -> Option<Contact> {
+        let index = self.bucket_index(node_id)?;
+        let bucket = &mut self.buckets[index];
+        let position = bucket.iter().position(|known| known.node_id == *node_id)?;
+        bucket.remove(position)
+    }
+
+    pub fn get(&self, node_id: &NodeId) -> Option<&Contact> {
+        self.buckets[self.bucket_index(node_id)?]
+            .iter()
+            .find(|known| known.node_id == *node_id)
+    }
+
+    /// Up to `count` contacts, ordered by their distance to `target`
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<Contact> {
+        let mut contacts: Vec<&Contact> = self.buckets.iter().flatten().collect();
+        contacts.sort_by_key(|contact| Distance::between(&contact.node_id, target));
+        contacts.into_iter().take(count).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.iter().all(VecDeque::is_empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::RawCertificate;
+    use crate::data::NodeId;
+    use crate::dht::routing::{Contact, Distance, RoutingTable};
+
+    fn node_id(first: u8, last: u8) -> NodeId {
+        let mut id = [0; 32];
+        id[0] = first;
+        id[31] = last;
+        NodeId::from(id)
+    }
+
+    fn contact(node_id: NodeId) -> Contact {
+        Contact {
+            node_id,
+            certificate: RawCertificate::from_der(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        let zero = node_id(0, 0);
+        assert_eq!(Distance::between(&zero, &zero).leading_zeros(), 256);
+        assert_eq!(
+            Distance::between(&zero, &node_id(0x80, 0)).leading_zeros(),
+            0
+        );
+        assert_eq!(
+            Distance::between(&zero, &node_id(0, 1)).leading_zeros(),
+            255
+        );
+        assert!(
+            Distance::between(&zero, &node_id(0, 0xff)) < Distance::between(&zero, &node_id(1, 0))
+        );
+    }
+
+    #[test]
+    fn test_buckets() {
+        let mut table = RoutingTable::new(node_id(0, 0), 2);
+        assert_eq!(table.bucket_index(&node_id(0, 0)), None);
+        assert_eq!(table.bucket_index(&node_id(0x80, 0)), Some(255));
+        assert_eq!(table.bucket_index(&node_id(0, 1)), Some(0));
+        assert!(!table.insert(contact(node_id(0, 0))));
+
+        assert!(table.insert(contact(node_id(0x80, 0))));
+        assert!(table.insert(contact(node_id(0x81, 0))));
+        // the bucket is full, known contacts are kept
+        assert!(!table.insert(contact(node_id(0x82, 0))));
+        assert!(!table.has_room(&node_id(0x82, 0)));
+        assert!(table.has_room(&node_id(0x81, 0)));
+        assert!(table.insert(contact(node_id(0x80, 0))));
+        assert!(table.insert(contact(node_id(0x40, 0))));
+        assert_eq!(table.len(), 3);
+
+        assert!(table.remove(&node_id(0x81, 0)).is_some());
+        assert!(table.get(&node_id(0x81, 0)).is_none());
+        assert!(table.insert(contact(node_id(0x82, 0))));
+    }
+
+    #[test]
+    fn test_id_in_bucket() {
+        let mut table = RoutingTable::new(node_id(0x55, 0xaa), 20);
+        assert_eq!(table.closest_bucket(), None);
+        table.insert(contact(node_id(0x55, 0xab)));
+        assert_eq!(table.closest_bucket(), Some(0));
+
+        for index in 0..256 {
+            for random in &[[0x00; 32], [0xff; 32], [0x5a; 32]] {
+                let id = table.id_in_bucket(index, *random);
+                assert_eq!(table.bucket_index(&id), Some(index));
+            }
+        }
+    }
+
+    #[test]
+    fn test_closest() {
+        let mut table = RoutingTable::new(node_id(0, 0), 20);
+        for first in 1..=16 {
+            table.insert(contact(node_id(first, 0)));
+        }
+        let closest: Vec<NodeId> = table
+            .closest(&node_id(0x05, 1), 3)
+            .into_iter()
+            .map(|contact| contact.node_id)
+            .collect();
+        assert_eq!(
+            closest,
+            vec![node_id(0x05, 0), node_id(0x04, 0), node_id(0x07, 0)]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Cold table
+//!
+//! A freshly booted node does not know any other node yet.
+//! The cold table is a file with PEM encoded certificates of other nodes,
+//! which is used to find nodes to connect to.
+//! It is rewritten from the warm table periodically and on shutdown.
+//!
+//! Certificates are only loaded if their signature is valid and the cold table time
+//! of the node metadata, counted from `not_before`, is not over yet.
+
+use crate::certificate::{Clock, RawCertificate, ValidityPolicy};
+use crate::directory::store::{Store, StoreEntry};
+use crate::file::write_atomically;
+use log::warn;
+use pem::Pem;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time;
+
+/// PEM tag of a certificate
+const PEM_TAG_CERTIFICATE: &str = "CERTIFICATE";
+
+/// File backed cold table
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ColdTable {
+    path: PathBuf,
+}
+
+impl ColdTable {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ColdTable { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads all valid certificates whose cold table time is not over
+    ///
+    /// Invalid certificates are skipped. A missing file is treated as an empty cold table.
+    pub fn load<C: Clock>(&self, policy: &ValidityPolicy<C>) -> io::Result<Vec<StoreEntry>> {
+        let content = match fs::read(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let now = policy.clock.now();
+
+        let mut entries = Vec::new();
+        for pem in pem::parse_many(content) {
+            if pem.tag != PEM_TAG_CERTIFICATE {
+                continue;
+            }
+            let certificate = RawCertificate::from_der(pem.contents);
+            match StoreEntry::decode(certificate, policy) {
+                Ok((_, entry)) if entry.cold_expires_at() > now => entries.push(entry),
+                Ok(_) => {}
+                Err(err) => warn!("skipping certificate in {}: {}", self.path.display(), err),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Replaces the cold table with the given certificates
+    ///
+    /// The file is replaced using [`write_atomically`].
+    pub fn save<'a>(
+        &self,
+        certificates: impl IntoIterator<Item = &'a RawCertificate>,
+    ) -> io::Result<()> {
+        let pems: Vec<Pem> = certificates
+            .into_iter()
+            .map(|certificate| Pem {
+                tag: PEM_TAG_CERTIFICATE.to_string(),
+                contents: certificate.der().to_vec(),
+            })
+            .collect();
+
+        write_atomically(&self.path, pem::encode_many(&pems).as_bytes())
+    }
+
+    /// Replaces the cold table with all certificates of the warm table
+    ///
+    /// Tombstones are not written, as they can not be used to find other nodes.
+    pub fn save_store<C: Clock>(&self, store: &Store<C>) -> io::Result<()> {
+        self.save(
+            store
+                .iter()
+                .map(|(_, entry)| entry)
+                .filter(|entry| !entry.is_tombstone())
+                .map(|entry| &entry.certificate),
+        )
+    }
+
+    /// Rewrites the cold table in the given interval
+    ///
+    /// `snapshot` returns the certificates to write, usually from the warm table.
+    /// Only returns if writing the cold table failed.
+    pub async fn persist_periodically<F>(
+        &self,
+        period: time::Duration,
+        mut snapshot: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut() -> Vec<RawCertificate>,
+    {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // the first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.save(&snapshot())?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{FixedClock, NodeMetadata, RawCertificate, ValidityPolicy};
+    use crate::directory::cold::ColdTable;
+    use crate::directory::store::Store;
+    use crate::test_util::{self, start};
+    use chrono::Duration;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn sign(cold_seconds: Option<u64>, reachable: bool) -> RawCertificate {
+        let data = test_util::data(NodeMetadata {
+            maximum_cold_table_seconds: cold_seconds,
+            ..NodeMetadata::default()
+        });
+        let data = if reachable { data } else { data.leave() };
+        data.sign_with_validity(&test_util::private_key(), start(), Duration::days(7))
+            .unwrap()
+    }
+
+    fn temporary_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "globalvpn-cold-{}-{}.pem",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_save_load() {
+        let table = ColdTable::new(temporary_path("save-load"));
+        let policy = |hours| ValidityPolicy::new(FixedClock(start() + Duration::hours(hours)));
+        let (short, long) = (sign(Some(3600), true), sign(None, true));
+
+        table.save(vec![&short, &long]).unwrap();
+        let loaded: Vec<_> = table
+            .load(&policy(0))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.certificate)
+            .collect();
+        assert_eq!(loaded, vec![short, long.clone()]);
+
+        // the cold table time of the first certificate is over
+        let loaded: Vec<_> = table
+            .load(&policy(2))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.certificate)
+            .collect();
+        assert_eq!(loaded, vec![long]);
+
+        fs::remove_file(table.path()).unwrap();
+        assert!(table.load(&policy(0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_skip_invalid_certificates() {
+        let table = ColdTable::new(temporary_path("invalid"));
+        let valid = sign(None, true);
+        let mut tampered = sign(None, true).der().to_vec();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        let tampered = RawCertificate::from_der(tampered);
+
+        table.save(vec![&tampered, &valid]).unwrap();
+        let entries = table
+            .load(&ValidityPolicy::new(FixedClock(start())))
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].certificate, valid);
+        fs::remove_file(table.path()).unwrap();
+    }
+
+    #[test]
+    fn test_save_store() {
+        let table = ColdTable::new(temporary_path("store"));
+        let policy = ValidityPolicy::new(FixedClock(start()));
+        let mut store = Store::with_policy(policy.clone());
+        let (reachable, tombstone) = (sign(None, true), sign(None, false));
+        store.insert(reachable.clone()).unwrap();
+        store.insert(tombstone).unwrap();
+
+        table.save_store(&store).unwrap();
+        let entries = table.load(&policy).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].certificate, reachable);
+        fs::remove_file(table.path()).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_persist_periodically() {
+        let table = ColdTable::new(temporary_path("periodic"));
+        let certificate = sign(None, true);
+        let snapshot = vec![certificate.clone()];
+
+        let persist =
+            table.persist_periodically(std::time::Duration::from_secs(60), || snapshot.clone());
+        let result = tokio::time::timeout(std::time::Duration::from_secs(90), persist).await;
+        assert!(result.is_err());
+
+        let entries = table
+            .load(&ValidityPolicy::new(FixedClock(start())))
+            .unwrap();
+        assert_eq!(entries[0].certificate, certificate);
+        fs::remove_file(table.path()).unwrap();
+    }
+}
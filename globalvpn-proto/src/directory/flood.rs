@@ -0,0 +1,429 @@
+//! Flooding of certificate updates between directory nodes
+//!
+//! A certificate received in an UPDATE packet is inserted into the [`Store`].
+//! Only if it changed the store, it is forwarded to all connected directory peers
+//! except the one it was received from.
+//! This includes a certificate moving a node out of the area of the store,
+//! see [`InsertOutcome::Removed`].
+//!
+//! A certificate changes the store of a node at most once,
+//! so every node forwards it at most once and the flooding terminates
+//! even if the directory nodes are connected in cycles.
+//!
+//! Flooding never waits for a peer, so two directory nodes flooding to each other
+//! can not block each other.
+//! A peer whose queue is full misses the update and is marked for a resync instead,
+//! see [`Flooding::take_resync`].
+
+use crate::certificate::{CertificateResult, Clock, RawCertificate, SystemClock};
+use crate::data::NodeId;
+use crate::directory::store::{InsertOutcome, Store};
+use crate::protocol::frame::Frame;
+use crate::protocol::update::UpdatePacket;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// Certificate store of a directory node together with its connected directory peers
+#[derive(Debug)]
+pub struct Flooding<C = SystemClock> {
+    store: Store<C>,
+    peers: HashMap<NodeId, mpsc::Sender<Frame>>,
+    resync: HashSet<NodeId>,
+}
+
+impl Default for Flooding<SystemClock> {
+    fn default() -> Self {
+        Flooding::new(Store::default())
+    }
+}
+
+impl<C: Clock> Flooding<C> {
+    pub fn new(store: Store<C>) -> Self {
+        Flooding {
+            store,
+            peers: HashMap::new(),
+            resync: HashSet::new(),
+        }
+    }
+
+    pub fn store(&self) -> &Store<C> {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut Store<C> {
+        &mut self.store
+    }
+
+    /// Adds a connected directory peer
+    ///
+    /// `sender` is usually [`crate::protocol::connection::Connection::sender`].
+    pub fn add_peer(&mut self, peer: NodeId, sender: mpsc::Sender<Frame>) {
+        self.peers.insert(peer, sender);
+    }
+
+    pub fn remove_peer(&mut self, peer: &NodeId) -> bool {
+        self.resync.remove(peer);
+        self.peers.remove(peer).is_some()
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &NodeId> {
+        self.peers.keys()
+    }
+
+    /// Checks if `peer` missed updates and has to be resynchronized
+    pub fn needs_resync(&self, peer: &NodeId) -> bool {
+        self.resync.contains(peer)
+    }
+
+    /// Resynchronizations of all peers which missed updates
+    ///
+    /// Each resync sends the whole warm table to the peer, see [`Resync::send`],
+    /// including the certificates which moved nodes out of the area.
+    /// Certificates the peer already knows do not change its store and are not flooded again.
+    pub fn take_resync(&mut self) -> Vec<Resync> {
+        let frames: Vec<Frame> = self
+            .store
+            .iter()
+            .chain(self.store.iter_moved())
+            .map(|(_, entry)| Frame::Update(UpdatePacket::new(entry.certificate.clone())))
+            .collect();
+        let peers = &self.peers;
+        self.resync
+            .drain()
+            .filter_map(|peer| {
+                peers.get(&peer).map(|sender| Resync {
+                    peer,
+                    sender: sender.clone(),
+                    frames: frames.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Stores a certificate which was not received from a directory peer
+    /// and floods it to all peers
+    pub fn publish(&mut self, certificate: RawCertificate) -> CertificateResult<InsertOutcome> {
+        self.flood(None, certificate)
+    }
+
+    /// Handles an UPDATE packet received from a directory peer
+    pub fn receive(
+        &mut self,
+        from: &NodeId,
+        packet: UpdatePacket,
+    ) -> CertificateResult<InsertOutcome> {
+        self.flood(Some(from), packet.certificate)
+    }
+
+    fn flood(
+        &mut self,
+        from: Option<&NodeId>,
+        certificate: RawCertificate,
+    ) -> CertificateResult<InsertOutcome> {
+        let outcome = self.store.insert(certificate.clone())?;
+        if !outcome.is_changed() {
+            return Ok(outcome);
+        }
+
+        let frame = Frame::Update(UpdatePacket::new(certificate));
+        let mut closed = Vec::new();
+        for (peer, sender) in &self.peers {
+            if Some(peer) == from {
+                continue;
+            }
+            match sender.try_send(frame.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "queue of directory peer {} is full, marking it for resync",
+                        peer
+                    );
+                    self.resync.insert(*peer);
+                }
+                Err(TrySendError::Closed(_)) => closed.push(*peer),
+            }
+        }
+        for peer in closed {
+            self.remove_peer(&peer);
+        }
+        Ok(outcome)
+    }
+}
+
+/// Warm table resynchronization of a directory peer which missed updates
+#[derive(Debug)]
+pub struct Resync {
+    peer: NodeId,
+    sender: mpsc::Sender<Frame>,
+    frames: Vec<Frame>,
+}
+
+impl Resync {
+    pub fn peer(&self) -> &NodeId {
+        &self.peer
+    }
+
+    /// Sends the warm table, waiting for space in the queue of the peer
+    ///
+    /// Should run in its own task, so that flooding is not blocked meanwhile.
+    /// Returns `false` if the peer closed the connection.
+    pub async fn send(self) -> bool {
+        for frame in self.frames {
+            if self.sender.send(frame).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{NodeMetadata, RawCertificate};
+    use crate::data::NodeId;
+    use crate::directory::flood::Flooding;
+    use crate::directory::store::{InsertOutcome, Store};
+    use crate::protocol::frame::Frame;
+    use crate::test_util::{private_key, sign, sign_from_now};
+    use chrono::{Duration, Utc};
+    use tokio::sync::mpsc;
+
+    struct TestNode {
+        flooding: Flooding,
+        inbound: Vec<(NodeId, mpsc::Receiver<Frame>)>,
+    }
+
+    /// Directory nodes connected by in-memory channels
+    struct TestNetwork {
+        nodes: Vec<TestNode>,
+        edges: usize,
+    }
+
+    impl TestNetwork {
+        fn new(size: usize, edges: &[(usize, usize)]) -> Self {
+            let mut network = TestNetwork {
+                nodes: (0..size)
+                    .map(|_| TestNode {
+                        flooding: Flooding::default(),
+                        inbound: Vec::new(),
+                    })
+                    .collect(),
+                edges: 0,
+            };
+            for &(a, b) in edges {
+                network.connect(a, b);
+                network.connect(b, a);
+                network.edges += 1;
+            }
+            network
+        }
+
+        fn id(index: usize) -> NodeId {
+            NodeId::from([index as u8; 32])
+        }
+
+        fn connect(&mut self, from: usize, to: usize) {
+            let (sender, receiver) = mpsc::channel(64);
+            self.nodes[from].flooding.add_peer(Self::id(to), sender);
+            self.nodes[to].inbound.push((Self::id(from), receiver));
+        }
+
+        /// Delivers all queued updates until no node forwards anything
+        ///
+        /// Returns the number of delivered updates.
+        fn run(&mut self) -> usize {
+            let mut delivered = 0;
+            loop {
+                let mut progress = false;
+                for node in &mut self.nodes {
+                    for (from, receiver) in &mut node.inbound {
+                        while let Ok(frame) = receiver.try_recv() {
+                            match frame {
+                                Frame::Update(packet) => {
+                                    node.flooding.receive(from, packet).unwrap();
+                                }
+                                other => panic!("unexpected frame {:?}", other),
+                            }
+                            delivered += 1;
+                            progress = true;
+                        }
+                    }
+                }
+                if !progress {
+                    return delivered;
+                }
+            }
+        }
+
+        fn assert_converged(&self, certificate: &RawCertificate) {
+            let node_id = certificate.node_id().unwrap();
+            for node in &self.nodes {
+                let entry = node.flooding.store().get(&node_id).unwrap();
+                assert_eq!(&entry.certificate, certificate);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_echo_to_sender() {
+        let mut network = TestNetwork::new(2, &[(0, 1)]);
+        let certificate = sign_from_now(&private_key(), 0);
+
+        let outcome = network.nodes[0].flooding.publish(certificate.clone());
+        assert_eq!(outcome.unwrap(), InsertOutcome::Inserted);
+        assert_eq!(network.run(), 1);
+        network.assert_converged(&certificate);
+    }
+
+    #[test]
+    fn test_converge_on_cycles() {
+        // ring with chords
+        let edges = [
+            (0, 1),
+            (1, 2),
+            (2, 3),
+            (3, 4),
+            (4, 5),
+            (5, 6),
+            (6, 7),
+            (7, 0),
+            (0, 4),
+            (2, 6),
+            (1, 5),
+        ];
+        let mut network = TestNetwork::new(8, &edges);
+        let key = private_key();
+        let (older, newer) = (sign_from_now(&key, 0), sign_from_now(&key, 60));
+
+        network.nodes[0].flooding.publish(older.clone()).unwrap();
+        // every node forwards the update at most once to every peer
+        assert!(network.run() <= 2 * network.edges);
+        network.assert_converged(&older);
+
+        network.nodes[5].flooding.publish(newer.clone()).unwrap();
+        assert!(network.run() <= 2 * network.edges);
+        network.assert_converged(&newer);
+
+        // outdated certificates are not flooded
+        let outcome = network.nodes[3].flooding.publish(older);
+        assert_eq!(outcome.unwrap(), InsertOutcome::Unchanged);
+        assert_eq!(network.run(), 0);
+        network.assert_converged(&newer);
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let edges: Vec<(usize, usize)> = (0..6)
+            .flat_map(|a| (a + 1..6).map(move |b| (a, b)))
+            .collect();
+        let mut network = TestNetwork::new(6, &edges);
+        let key = private_key();
+        let (older, newer) = (sign_from_now(&key, 0), sign_from_now(&key, 60));
+
+        network.nodes[0].flooding.publish(older).unwrap();
+        network.nodes[5].flooding.publish(newer.clone()).unwrap();
+        // each of the two updates is forwarded by every node at most once
+        assert!(network.run() <= 2 * 2 * network.edges);
+        network.assert_converged(&newer);
+    }
+
+    #[tokio::test]
+    async fn test_resync_full_peer() {
+        let mut a = Flooding::default();
+        let mut b = Flooding::default();
+        let (a_id, b_id) = (TestNetwork::id(0), TestNetwork::id(1));
+        let (sender, mut receiver) = mpsc::channel(1);
+        a.add_peer(b_id, sender);
+
+        let certificates: Vec<_> = (0..3).map(|_| sign_from_now(&private_key(), 0)).collect();
+        for certificate in &certificates {
+            a.publish(certificate.clone()).unwrap();
+        }
+        // only the first update fit into the queue
+        assert!(a.needs_resync(&b_id));
+        let mut resync = a.take_resync();
+        assert!(!a.needs_resync(&b_id));
+        assert_eq!(resync.len(), 1);
+        let resync = resync.pop().unwrap();
+        assert_eq!(resync.peer(), &b_id);
+
+        let sending = tokio::spawn(resync.send());
+        let mut outcomes = Vec::new();
+        for _ in 0..1 + certificates.len() {
+            match receiver.recv().await.unwrap() {
+                Frame::Update(packet) => outcomes.push(b.receive(&a_id, packet).unwrap()),
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+        assert!(sending.await.unwrap());
+        let changed = outcomes
+            .iter()
+            .filter(|outcome| outcome.is_changed())
+            .count();
+        assert_eq!(changed, certificates.len());
+        for certificate in &certificates {
+            let entry = b.store().get(&certificate.node_id().unwrap()).unwrap();
+            assert_eq!(&entry.certificate, certificate);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resync_moved_node() {
+        let area_store = || Store::default().with_area("eu".parse().unwrap());
+        let (mut a, mut b) = (Flooding::new(area_store()), Flooding::new(area_store()));
+        let (a_id, b_id) = (TestNetwork::id(0), TestNetwork::id(1));
+        let x = private_key();
+        let sign = |key: &[u8], area: &str, seconds| {
+            let metadata = NodeMetadata {
+                area: Some(area.parse().unwrap()),
+                ..NodeMetadata::default()
+            };
+            sign(key, metadata, Utc::now() + Duration::seconds(seconds))
+        };
+        let x_old = sign(&x, "eu", 0);
+        a.publish(x_old.clone()).unwrap();
+        b.publish(x_old).unwrap();
+
+        let (sender, mut receiver) = mpsc::channel(1);
+        a.add_peer(b_id, sender);
+        let filler = sign(&private_key(), "eu", 0);
+        a.publish(filler.clone()).unwrap();
+        // the move does not fit into the queue
+        assert_eq!(
+            a.publish(sign(&x, "us", 60)).unwrap(),
+            InsertOutcome::Removed
+        );
+        let mut resync = a.take_resync();
+        assert_eq!(resync.len(), 1);
+        // the channel is closed once the resync is sent
+        a.remove_peer(&b_id);
+
+        let sending = tokio::spawn(resync.pop().unwrap().send());
+        let mut outcomes = Vec::new();
+        while let Some(frame) = receiver.recv().await {
+            match frame {
+                Frame::Update(packet) => outcomes.push(b.receive(&a_id, packet).unwrap()),
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+        assert!(sending.await.unwrap());
+        assert!(outcomes.contains(&InsertOutcome::Removed));
+        assert_eq!(b.store().len(), 1);
+        assert!(b.store().get(&filler.node_id().unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_remove_closed_peer() {
+        let mut network = TestNetwork::new(3, &[(0, 1), (0, 2)]);
+        network.nodes[2].inbound.clear();
+
+        network.nodes[0]
+            .flooding
+            .publish(sign_from_now(&private_key(), 0))
+            .unwrap();
+        let peers: Vec<_> = network.nodes[0].flooding.peers().copied().collect();
+        assert_eq!(peers, vec![TestNetwork::id(1)]);
+    }
+}
@@ -0,0 +1,225 @@
+//! Hybrid dictionary nodes
+//!
+//! A hybrid dictionary node holds the full state of a single [`Area`]
+//! in a [`Store`] limited to that area, see [`Store::with_area`].
+//! The store is kept up to date like the global table, e.g. by flooding
+//! and synchronizing with the other dictionary nodes of the area.
+//!
+//! A LOOKUP packet only contains the NodeId, not the area of the node.
+//! As the store holds every node of the area, a node missing in the store
+//! is outside of the area, so the lookup is forwarded to the designated upstream,
+//! usually a dictionary node holding the global table.
+//! Forwarding follows the rules of the [`DictionaryProxy`].
+//! Nodes of the area which did not reach the store yet are forwarded as well.
+//! A node which moved out of the area is answered with the certificate of the move
+//! while the store keeps it, see [`Store::iter_moved`].
+
+use crate::certificate::{Clock, SystemClock};
+use crate::data::Area;
+use crate::directory::lookup::DictionaryClient;
+use crate::directory::proxy::DictionaryProxy;
+use crate::directory::store::Store;
+use crate::protocol::frame::Frame;
+use crate::protocol::lookup::{LookupPacket, LookupResponsePacket};
+use std::future::Future;
+use std::sync::Arc;
+
+/// Answers lookups of nodes in its area locally and proxies all others
+#[derive(Debug)]
+pub struct HybridDictionary<C = SystemClock> {
+    area: Area,
+    upstream: DictionaryProxy<C>,
+}
+
+impl<C: Clock> HybridDictionary<C> {
+    /// Creates a hybrid dictionary for `area`, forwarding all other lookups to `upstream`
+    pub fn new(area: Area, upstream: Arc<DictionaryClient<C>>) -> Self {
+        let proxy = DictionaryProxy::new();
+        proxy.add_upstream(upstream);
+        HybridDictionary {
+            area,
+            upstream: proxy,
+        }
+    }
+
+    pub fn area(&self) -> &Area {
+        &self.area
+    }
+
+    /// Answers a LOOKUP packet
+    ///
+    /// `store` has to be limited to the area of this dictionary.
+    /// Nodes in the store and nodes which moved out of the area are answered immediately,
+    /// the latter with the certificate of the move.
+    /// All other nodes are looked up at the upstream, including nodes of the area
+    /// the store did not learn about yet, e.g. before it was synchronized.
+    /// The store is not borrowed while the upstream is asked.
+    pub fn answer<'a>(
+        &'a self,
+        store: &Store<C>,
+        packet: LookupPacket,
+    ) -> impl Future<Output = Frame> + 'a {
+        debug_assert_eq!(store.area(), Some(&self.area));
+        let local = store
+            .get(&packet.node_id)
+            .or_else(|| store.get_moved(&packet.node_id))
+            .map(|entry| {
+                Frame::LookupResponse(LookupResponsePacket {
+                    request_id: packet.request_id,
+                    certificate: entry.certificate.clone(),
+                })
+            });
+        async move {
+            match local {
+                Some(frame) => frame,
+                None => self.upstream.answer(packet).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{NodeMetadata, RawCertificate};
+    use crate::data::{Area, NodeId};
+    use crate::directory::hybrid::HybridDictionary;
+    use crate::directory::lookup::{answer_lookup, DictionaryClient};
+    use crate::directory::store::{InsertOutcome, Store};
+    use crate::protocol::connection::{Connection, ConnectionConfig};
+    use crate::protocol::error::{ErrorPacket, ProtocolError};
+    use crate::protocol::frame::Frame;
+    use crate::protocol::lookup::{LookupPacket, LookupResponsePacket};
+    use crate::test_util;
+    use chrono::{Duration, Utc};
+    use std::convert::TryFrom;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Connects a client to a dictionary node holding the global table
+    fn connect_global(store: Store, requests: Arc<AtomicUsize>) -> Arc<DictionaryClient> {
+        let (a, b) = tokio::io::duplex(4096);
+        let mut server = Connection::spawn(a, ConnectionConfig::default());
+        tokio::spawn(async move {
+            while let Some(frame) = server.recv().await {
+                if let Frame::Lookup(packet) = frame {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    let _ = server.sender().send(answer_lookup(&store, &packet)).await;
+                }
+            }
+        });
+
+        let mut connection = Connection::spawn(b, ConnectionConfig::default());
+        let client = Arc::new(DictionaryClient::new(connection.sender()));
+        let router = client.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = connection.recv().await {
+                router.handle_frame(frame);
+            }
+            router.close();
+        });
+        client
+    }
+
+    fn sign(area: &str) -> RawCertificate {
+        sign_with_key(&test_util::private_key(), area, 0)
+    }
+
+    fn sign_with_key(private_key: &[u8], area: &str, seconds: i64) -> RawCertificate {
+        let metadata = NodeMetadata {
+            area: Some(area.parse().unwrap()),
+            ..NodeMetadata::default()
+        };
+        test_util::sign(
+            private_key,
+            metadata,
+            Utc::now() + Duration::seconds(seconds),
+        )
+    }
+
+    fn lookup(request_id: u32, node_id: NodeId) -> LookupPacket {
+        LookupPacket {
+            request_id,
+            hop_limit: 8,
+            node_id,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_dictionary() {
+        let (local, remote) = (sign("eu"), sign("us"));
+        let mut global = Store::default();
+        global.insert(local.clone()).unwrap();
+        global.insert(remote.clone()).unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let area: Area = "eu".parse().unwrap();
+        let hybrid = HybridDictionary::new(
+            area.clone(),
+            connect_global(global.clone(), requests.clone()),
+        );
+        let mut store = Store::default().with_area(area);
+        for certificate in global.iter().map(|(_, entry)| entry.certificate.clone()) {
+            store.insert(certificate).unwrap();
+        }
+        assert_eq!(store.len(), 1);
+        assert_eq!(
+            store.insert(remote.clone()).unwrap(),
+            InsertOutcome::OutOfArea
+        );
+
+        // nodes of the area are answered without asking the upstream
+        let local_id = local.node_id().unwrap();
+        assert_eq!(
+            hybrid.answer(&store, lookup(1, local_id)).await,
+            answer_lookup(&store, &lookup(1, local_id))
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        let remote_id = remote.node_id().unwrap();
+        assert_eq!(
+            hybrid.answer(&store, lookup(2, remote_id)).await,
+            answer_lookup(&global, &lookup(2, remote_id))
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        // the answer of the upstream is cached
+        hybrid.answer(&store, lookup(3, remote_id)).await;
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let unknown = NodeId::from([0x42; 32]);
+        let not_found = ProtocolError::NodeNotFound {
+            request_id: 4,
+            node_id: unknown,
+        };
+        assert_eq!(
+            hybrid.answer(&store, lookup(4, unknown)).await,
+            Frame::Error(ErrorPacket::try_from(&not_found).unwrap())
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_answer_moved_node() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let area: Area = "eu".parse().unwrap();
+        let hybrid = HybridDictionary::new(
+            area.clone(),
+            connect_global(Store::default(), requests.clone()),
+        );
+        let mut store = Store::default().with_area(area);
+        let key = test_util::private_key();
+        store.insert(sign_with_key(&key, "eu", 0)).unwrap();
+        let moved = sign_with_key(&key, "us", 60);
+        assert_eq!(store.insert(moved.clone()).unwrap(), InsertOutcome::Removed);
+
+        // the certificate of the move is known without asking the upstream
+        let node_id = moved.node_id().unwrap();
+        assert_eq!(
+            hybrid.answer(&store, lookup(1, node_id)).await,
+            Frame::LookupResponse(LookupResponsePacket {
+                request_id: 1,
+                certificate: moved,
+            })
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+}
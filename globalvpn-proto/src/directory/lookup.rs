@@ -0,0 +1,389 @@
+//! Lookup of the certificate of a single node
+//!
+//! A node which is not a directory node itself asks a directory node
+//! for the reachability information of another node, see [`crate::protocol::lookup`].
+//! The directory node answers from its [`Store`], see [`answer_lookup`].
+//!
+//! Answers are verified and cached by the [`DictionaryClient`]
+//! until the warm table time of the certificate is over.
+//!
+//! Outstanding lookups are identified by their request ID together with the requested
+//! NodeId, as forwarded lookups keep the request ID chosen by another node.
+//! The timeout is shared by all hops of a lookup: a lookup with hop limit `h` waits
+//! `(h + 1) / (DEFAULT_LOOKUP_HOP_LIMIT + 1)` of the timeout,
+//! so a forwarding node gives up before the node which asked it.
+
+use crate::certificate::{CertificateError, Clock, RawCertificate, SystemClock, ValidityPolicy};
+use crate::data::NodeId;
+use crate::directory::store::{Store, StoreEntry};
+use crate::protocol::error::{ErrorPacket, ProtocolError};
+use crate::protocol::frame::Frame;
+use crate::protocol::lookup::{LookupPacket, LookupResponsePacket, DEFAULT_LOOKUP_HOP_LIMIT};
+use log::debug;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+/// Time to wait for the answer of a directory node to a lookup with the default hop limit
+pub const DEFAULT_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type LookupResult<T> = Result<T, LookupError>;
+
+#[derive(Error, Debug)]
+pub enum LookupError {
+    #[error("Certificate error: {0}")]
+    Certificate(#[from] CertificateError),
+    #[error("Protocol error: {0}")]
+    Protocol(#[from] ProtocolError),
+    #[error("No answer from directory node in time")]
+    Timeout,
+    #[error("Lookup {request_id} of {node_id} is already outstanding")]
+    DuplicateRequestId { request_id: u32, node_id: NodeId },
+    #[error("Directory node answered with a certificate of {node_id} which is no longer cached")]
+    Expired { node_id: NodeId },
+}
+
+/// Answers a LOOKUP packet from the store of a directory node
+///
+/// Returns a LOOKUP_RESPONSE frame, or an ERROR frame if the node is unknown.
+/// Tombstones are returned as well, so the requesting node learns that the node left.
+pub fn answer_lookup<C: Clock>(store: &Store<C>, packet: &LookupPacket) -> Frame {
+    match store.get(&packet.node_id) {
+        Some(entry) => Frame::LookupResponse(LookupResponsePacket {
+            request_id: packet.request_id,
+            certificate: entry.certificate.clone(),
+        }),
+        None => {
+            let err = ProtocolError::NodeNotFound {
+                request_id: packet.request_id,
+                node_id: packet.node_id,
+            };
+            Frame::Error(ErrorPacket::try_from(&err).expect("error can be sent"))
+        }
+    }
+}
+
+/// Request ID and requested NodeId of an outstanding lookup
+type PendingKey = (u32, NodeId);
+
+/// Client for looking up certificates at a directory node
+///
+/// Lookups are sent using the sender of a connection.
+/// All frames received on the connection have to be passed to
+/// [`DictionaryClient::handle_frame`], which completes the outstanding lookups.
+#[derive(Debug)]
+pub struct DictionaryClient<C = SystemClock> {
+    sender: mpsc::Sender<Frame>,
+    pending: Mutex<HashMap<PendingKey, oneshot::Sender<Option<RawCertificate>>>>,
+    rng: SystemRandom,
+    cache: Mutex<Store<C>>,
+    timeout: Duration,
+}
+
+impl DictionaryClient<SystemClock> {
+    /// `sender` is usually [`crate::protocol::connection::Connection::sender`]
+    pub fn new(sender: mpsc::Sender<Frame>) -> Self {
+        DictionaryClient::with_policy(sender, ValidityPolicy::default())
+    }
+}
+
+impl<C: Clock> DictionaryClient<C> {
+    /// Creates a client verifying and caching certificates with the given policy
+    pub fn with_policy(sender: mpsc::Sender<Frame>, policy: ValidityPolicy<C>) -> Self {
+        DictionaryClient {
+            sender,
+            pending: Mutex::new(HashMap::new()),
+            rng: SystemRandom::new(),
+            cache: Mutex::new(Store::with_policy(policy)),
+            timeout: DEFAULT_LOOKUP_TIMEOUT,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Looks up the certificate of a node
+    ///
+    /// Cached certificates are returned until their warm table time is over,
+    /// otherwise the directory node is asked.
+    /// Returns `None` if the directory node does not know the node.
+    /// Fails with [`LookupError::Expired`] if the warm table time of the answer is over.
+    pub async fn lookup(&self, node_id: NodeId) -> LookupResult<Option<StoreEntry>> {
+        loop {
+            let mut request_id = [0; 4];
+            self.rng
+                .fill(&mut request_id)
+                .expect("system random number generator failed");
+            let packet = LookupPacket {
+                request_id: u32::from_be_bytes(request_id),
+                hop_limit: DEFAULT_LOOKUP_HOP_LIMIT,
+                node_id,
+            };
+            match self.forward(packet).await {
+                // very unlikely, choose another request ID
+                Err(LookupError::DuplicateRequestId { .. }) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends a lookup with the request ID and hop limit of the given packet
+    ///
+    /// Used by a [`crate::directory::DictionaryProxy`] to forward lookups.
+    /// As in [`DictionaryClient::lookup`], cached certificates are returned without asking.
+    pub async fn forward(&self, packet: LookupPacket) -> LookupResult<Option<StoreEntry>> {
+        let node_id = packet.node_id;
+        if let Some(entry) = self.cached(&node_id) {
+            return Ok(Some(entry));
+        }
+
+        let key = (packet.request_id, node_id);
+        let (answer_sender, answer) = oneshot::channel();
+        match self.pending.lock().unwrap().entry(key) {
+            Entry::Occupied(_) => {
+                return Err(LookupError::DuplicateRequestId {
+                    request_id: packet.request_id,
+                    node_id,
+                })
+            }
+            Entry::Vacant(entry) => entry.insert(answer_sender),
+        };
+        let result = self.request(packet, answer).await;
+        self.pending.lock().unwrap().remove(&key);
+
+        let certificate = match result? {
+            Some(certificate) => certificate,
+            None => return Ok(None),
+        };
+        // answers are matched by the NodeId of their certificate, see `handle_frame`
+        let mut cache = self.cache.lock().unwrap();
+        cache.expire();
+        cache.insert(certificate)?;
+        match cache.get(&node_id) {
+            Some(entry) => Ok(Some(entry.clone())),
+            None => Err(LookupError::Expired { node_id }),
+        }
+    }
+
+    /// Cached certificate of a node, if its warm table time is not over
+    pub fn cached(&self, node_id: &NodeId) -> Option<StoreEntry> {
+        self.cache.lock().unwrap().get(node_id).cloned()
+    }
+
+    async fn request(
+        &self,
+        packet: LookupPacket,
+        answer: oneshot::Receiver<Option<RawCertificate>>,
+    ) -> LookupResult<Option<RawCertificate>> {
+        self.sender
+            .send(Frame::Lookup(packet))
+            .await
+            .map_err(|_err| ProtocolError::ConnectionClosed)?;
+        match tokio::time::timeout(self.hop_timeout(packet.hop_limit), answer).await {
+            Ok(Ok(certificate)) => Ok(certificate),
+            Ok(Err(_closed)) => Err(ProtocolError::ConnectionClosed.into()),
+            Err(_elapsed) => Err(LookupError::Timeout),
+        }
+    }
+
+    /// Time to wait for the answer to a lookup with the given hop limit
+    fn hop_timeout(&self, hop_limit: u8) -> Duration {
+        let hops = u32::from(hop_limit.min(DEFAULT_LOOKUP_HOP_LIMIT)) + 1;
+        self.timeout * hops / (u32::from(DEFAULT_LOOKUP_HOP_LIMIT) + 1)
+    }
+
+    /// Handles a frame received from the directory node
+    ///
+    /// Returns the frame if it is not an answer to a lookup.
+    pub fn handle_frame(&self, frame: Frame) -> Option<Frame> {
+        let (request_id, node_id, certificate) = match frame {
+            Frame::LookupResponse(packet) => match packet.certificate.node_id() {
+                Ok(node_id) => (packet.request_id, node_id, Some(packet.certificate)),
+                Err(err) => {
+                    debug!("dropping answer to lookup {}: {}", packet.request_id, err);
+                    return None;
+                }
+            },
+            Frame::Error(packet) => match ProtocolError::from(packet.clone()) {
+                ProtocolError::NodeNotFound {
+                    request_id,
+                    node_id,
+                } => (request_id, node_id, None),
+                _ => return Some(Frame::Error(packet)),
+            },
+            frame => return Some(frame),
+        };
+        match self.pending.lock().unwrap().remove(&(request_id, node_id)) {
+            Some(answer) => {
+                let _ = answer.send(certificate);
+            }
+            // the lookup may have timed out in the meantime
+            None => debug!("dropping answer to unknown lookup {}", request_id),
+        }
+        None
+    }
+
+    /// Fails all outstanding lookups, used after the connection was closed
+    pub fn close(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{NodeMetadata, RawCertificate, ValidityPolicy};
+    use crate::data::NodeId;
+    use crate::directory::lookup::{answer_lookup, DictionaryClient, LookupError};
+    use crate::directory::store::Store;
+    use crate::protocol::connection::{Connection, ConnectionConfig};
+    use crate::protocol::frame::Frame;
+    use crate::protocol::lookup::{LookupPacket, LookupResponsePacket};
+    use crate::test_util::{self, private_key, start, TestClock};
+    use chrono::{DateTime, Duration, Utc};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    fn sign(private_key: &[u8], not_before: DateTime<Utc>) -> RawCertificate {
+        let metadata = NodeMetadata {
+            maximum_warm_table_seconds: Some(60),
+            ..NodeMetadata::default()
+        };
+        test_util::sign(private_key, metadata, not_before)
+    }
+
+    #[tokio::test]
+    async fn test_lookup_over_connection() {
+        let certificate = sign(&private_key(), Utc::now());
+        let node_id = certificate.node_id().unwrap();
+        let mut store = Store::default();
+        store.insert(certificate.clone()).unwrap();
+
+        let (a, b) = tokio::io::duplex(4096);
+        let mut directory = Connection::spawn(a, ConnectionConfig::default());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let directory_requests = requests.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = directory.recv().await {
+                if let Frame::Lookup(packet) = frame {
+                    directory_requests.fetch_add(1, Ordering::SeqCst);
+                    directory
+                        .send(answer_lookup(&store, &packet))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let mut connection = Connection::spawn(b, ConnectionConfig::default());
+        let client = Arc::new(DictionaryClient::new(connection.sender()));
+        let router = client.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = connection.recv().await {
+                assert_eq!(router.handle_frame(frame), None);
+            }
+            router.close();
+        });
+
+        let entry = client.lookup(node_id).await.unwrap().unwrap();
+        assert_eq!(entry.certificate, certificate);
+        assert!(!entry.is_tombstone());
+        // the second lookup is answered from the cache
+        let cached = client.lookup(node_id).await.unwrap().unwrap();
+        assert_eq!(cached, entry);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let unknown = NodeId::from([0x42; 32]);
+        assert!(client.lookup(unknown).await.unwrap().is_none());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cache_respects_validity() {
+        let clock = TestClock::new(start());
+        let (sender, mut requests) = mpsc::channel(4);
+        let client = DictionaryClient::with_policy(sender, ValidityPolicy::new(&clock))
+            .with_timeout(std::time::Duration::from_secs(1));
+        let key = private_key();
+        let certificate = sign(&key, start());
+        let node_id = certificate.node_id().unwrap();
+
+        let answer = |requests: &mut mpsc::Receiver<Frame>, certificate: RawCertificate| {
+            let request_id = match requests.try_recv() {
+                Ok(Frame::Lookup(packet)) => packet.request_id,
+                other => panic!("unexpected request {:?}", other),
+            };
+            client.handle_frame(Frame::LookupResponse(LookupResponsePacket {
+                request_id,
+                certificate,
+            }))
+        };
+
+        let (entry, _) = tokio::join!(client.lookup(node_id), async {
+            tokio::task::yield_now().await;
+            answer(&mut requests, certificate.clone())
+        });
+        assert_eq!(entry.unwrap().unwrap().certificate, certificate);
+        assert!(client.lookup(node_id).await.unwrap().is_some());
+        assert!(requests.try_recv().is_err());
+
+        // the warm table time of the cached certificate is over
+        clock.0.set(start() + Duration::minutes(2));
+        let newer = sign(&key, start() + Duration::seconds(90));
+        let (entry, _) = tokio::join!(client.lookup(node_id), async {
+            tokio::task::yield_now().await;
+            answer(&mut requests, newer.clone())
+        });
+        assert_eq!(entry.unwrap().unwrap().certificate, newer);
+
+        // an answer with the certificate of another node does not match the lookup
+        clock.0.set(start() + Duration::minutes(3));
+        let other = sign(&private_key(), start() + Duration::minutes(3));
+        let (entry, unmatched) = tokio::join!(client.lookup(node_id), async {
+            tokio::task::yield_now().await;
+            answer(&mut requests, other)
+        });
+        assert_eq!(unmatched, None);
+        assert!(matches!(entry, Err(LookupError::Timeout)));
+
+        // a valid answer whose warm table time is over is not mistaken for an unknown node
+        clock.0.set(start() + Duration::minutes(5));
+        let (entry, _) = tokio::join!(client.lookup(node_id), async {
+            tokio::task::yield_now().await;
+            answer(&mut requests, newer)
+        });
+        assert!(matches!(entry, Err(LookupError::Expired { node_id: id }) if id == node_id));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lookup_timeout() {
+        let (sender, _requests) = mpsc::channel(4);
+        let client = DictionaryClient::new(sender).with_timeout(std::time::Duration::from_secs(9));
+        let started = tokio::time::Instant::now();
+        assert!(matches!(
+            client.lookup(NodeId::from([0x42; 32])).await,
+            Err(LookupError::Timeout)
+        ));
+        assert_eq!(started.elapsed(), std::time::Duration::from_secs(9));
+
+        // a forwarded lookup gives up earlier, in proportion to its remaining hops
+        let started = tokio::time::Instant::now();
+        let forwarded = LookupPacket {
+            request_id: 1,
+            hop_limit: 2,
+            node_id: NodeId::from([0x42; 32]),
+        };
+        assert!(matches!(
+            client.forward(forwarded).await,
+            Err(LookupError::Timeout)
+        ));
+        assert_eq!(started.elapsed(), std::time::Duration::from_secs(3));
+    }
+}
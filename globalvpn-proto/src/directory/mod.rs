@@ -0,0 +1,22 @@
+//! Directory nodes
+//!
+//! A directory node stores the certificates of all known nodes
+//! and floods new certificates to all other connected directory nodes.
+//! A hybrid directory node only stores the certificates of its own area,
+//! see [`HybridDictionary`].
+
+pub mod cold;
+pub mod flood;
+pub mod hybrid;
+pub mod lookup;
+pub mod proxy;
+pub mod store;
+pub mod sync;
+
+pub use cold::ColdTable;
+pub use flood::{Flooding, Resync};
+pub use hybrid::HybridDictionary;
+pub use lookup::DictionaryClient;
+pub use proxy::DictionaryProxy;
+pub use store::{InsertOutcome, Store, StoreEntry};
+pub use sync::WarmSync;
@@ -0,0 +1,305 @@
+//! Proxying of lookups to other dictionary nodes
+//!
+//! A dictionary node which does not hold the global table, e.g. a small edge node,
+//! forwards lookups it can not answer from its own [`Store`](crate::directory::Store)
+//! to its upstream dictionary nodes, which may forward them again.
+//!
+//! Forwarded lookups keep their request ID and their hop limit is decremented,
+//! see [`crate::protocol::lookup`].
+//! A lookup of the same node with the same request ID which is received again while
+//! it is still forwarded took a loop and is answered as not found,
+//! so the forwarding node continues with its next upstream.
+//!
+//! Answers of the upstream dictionary nodes are cached by their [`DictionaryClient`].
+
+use crate::certificate::{Clock, RawCertificate, SystemClock};
+use crate::data::NodeId;
+use crate::directory::lookup::DictionaryClient;
+use crate::protocol::error::{ErrorPacket, ProtocolError};
+use crate::protocol::frame::Frame;
+use crate::protocol::lookup::{LookupPacket, LookupResponsePacket};
+use log::debug;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+/// Forwards lookups to upstream dictionary nodes
+#[derive(Debug)]
+pub struct DictionaryProxy<C = SystemClock> {
+    upstreams: Mutex<Vec<Arc<DictionaryClient<C>>>>,
+    in_flight: Mutex<HashSet<(u32, NodeId)>>,
+}
+
+impl<C> Default for DictionaryProxy<C> {
+    fn default() -> Self {
+        DictionaryProxy {
+            upstreams: Mutex::new(Vec::new()),
+            in_flight: Mutex::new(HashSet::new()),
+        }
+    }
+}
+
+impl<C: Clock> DictionaryProxy<C> {
+    pub fn new() -> Self {
+        DictionaryProxy::default()
+    }
+
+    /// Adds an upstream dictionary node
+    ///
+    /// Upstreams are asked in the order they were added.
+    /// They can be added while lookups are answered.
+    pub fn add_upstream(&self, upstream: Arc<DictionaryClient<C>>) {
+        self.upstreams.lock().unwrap().push(upstream);
+    }
+
+    /// Answers a LOOKUP packet which could not be answered from the local store
+    ///
+    /// Returns a LOOKUP_RESPONSE frame if an upstream knows the node,
+    /// otherwise an ERROR frame, as [`crate::directory::lookup::answer_lookup`] does.
+    pub async fn answer(&self, packet: LookupPacket) -> Frame {
+        let node_id = packet.node_id;
+        let upstreams = self.upstreams.lock().unwrap().clone();
+        let cached = upstreams
+            .iter()
+            .find_map(|upstream| upstream.cached(&node_id));
+        if let Some(entry) = cached {
+            return response(&packet, entry.certificate);
+        }
+
+        if packet.hop_limit == 0 {
+            debug!(
+                "not forwarding lookup {}, hop limit reached",
+                packet.request_id
+            );
+            return not_found(&packet);
+        }
+        let key = (packet.request_id, node_id);
+        if !self.in_flight.lock().unwrap().insert(key) {
+            debug!("not forwarding lookup {}, loop detected", packet.request_id);
+            return not_found(&packet);
+        }
+
+        let forwarded = LookupPacket {
+            hop_limit: packet.hop_limit - 1,
+            ..packet
+        };
+        let mut answer = None;
+        for upstream in upstreams {
+            match upstream.forward(forwarded).await {
+                Ok(Some(entry)) => {
+                    answer = Some(entry.certificate);
+                    break;
+                }
+                Ok(None) => {}
+                Err(err) => debug!("forwarding lookup {} failed: {}", packet.request_id, err),
+            }
+        }
+        self.in_flight.lock().unwrap().remove(&key);
+
+        match answer {
+            Some(certificate) => response(&packet, certificate),
+            None => not_found(&packet),
+        }
+    }
+}
+
+fn response(packet: &LookupPacket, certificate: RawCertificate) -> Frame {
+    Frame::LookupResponse(LookupResponsePacket {
+        request_id: packet.request_id,
+        certificate,
+    })
+}
+
+fn not_found(packet: &LookupPacket) -> Frame {
+    let err = ProtocolError::NodeNotFound {
+        request_id: packet.request_id,
+        node_id: packet.node_id,
+    };
+    Frame::Error(ErrorPacket::try_from(&err).expect("error can be sent"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::RawCertificate;
+    use crate::data::NodeId;
+    use crate::directory::lookup::{answer_lookup, DictionaryClient};
+    use crate::directory::proxy::DictionaryProxy;
+    use crate::directory::store::Store;
+    use crate::protocol::connection::{Connection, ConnectionConfig};
+    use crate::protocol::frame::Frame;
+    use crate::protocol::lookup::LookupPacket;
+    use crate::test_util;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Dictionary node with an optional store, answering lookups over in-memory connections
+    #[derive(Default)]
+    struct TestDirectory {
+        store: Option<Store>,
+        proxy: DictionaryProxy,
+        requests: AtomicUsize,
+    }
+
+    impl TestDirectory {
+        fn with_certificate(certificate: RawCertificate) -> Arc<Self> {
+            let mut store = Store::default();
+            store.insert(certificate).unwrap();
+            Arc::new(TestDirectory {
+                store: Some(store),
+                ..TestDirectory::default()
+            })
+        }
+
+        async fn answer(&self, packet: LookupPacket) -> Frame {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            match &self.store {
+                Some(store) if store.get(&packet.node_id).is_some() => {
+                    answer_lookup(store, &packet)
+                }
+                _ => self.proxy.answer(packet).await,
+            }
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+
+        /// Connects a client to this directory node
+        fn connect(self: &Arc<Self>) -> Arc<DictionaryClient> {
+            let (a, b) = tokio::io::duplex(4096);
+            let mut server = Connection::spawn(a, ConnectionConfig::default());
+            let directory = self.clone();
+            tokio::spawn(async move {
+                while let Some(frame) = server.recv().await {
+                    if let Frame::Lookup(packet) = frame {
+                        let (directory, sender) = (directory.clone(), server.sender());
+                        tokio::spawn(async move {
+                            let _ = sender.send(directory.answer(packet).await).await;
+                        });
+                    }
+                }
+            });
+
+            let mut connection = Connection::spawn(b, ConnectionConfig::default());
+            let client = Arc::new(
+                DictionaryClient::new(connection.sender())
+                    .with_timeout(std::time::Duration::from_secs(5)),
+            );
+            let router = client.clone();
+            tokio::spawn(async move {
+                while let Some(frame) = connection.recv().await {
+                    router.handle_frame(frame);
+                }
+                router.close();
+            });
+            client
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_and_cache() {
+        let certificate = test_util::certificate();
+        let node_id = certificate.node_id().unwrap();
+        let global = TestDirectory::with_certificate(certificate.clone());
+        let edge = Arc::new(TestDirectory::default());
+        edge.proxy.add_upstream(global.connect());
+        let client = edge.connect();
+
+        let entry = client.lookup(node_id).await.unwrap().unwrap();
+        assert_eq!(entry.certificate, certificate);
+        assert_eq!(global.requests(), 1);
+
+        // the edge node answers from the cached answer of its upstream
+        let other_client = edge.connect();
+        let entry = other_client.lookup(node_id).await.unwrap().unwrap();
+        assert_eq!(entry.certificate, certificate);
+        assert_eq!((edge.requests(), global.requests()), (2, 1));
+
+        assert!(client
+            .lookup(NodeId::from([0x42; 32]))
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(global.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_hop_limit() {
+        let certificate = test_util::certificate();
+        let node_id = certificate.node_id().unwrap();
+        let global = TestDirectory::with_certificate(certificate.clone());
+        let middle = Arc::new(TestDirectory::default());
+        middle.proxy.add_upstream(global.connect());
+        let edge = Arc::new(TestDirectory::default());
+        edge.proxy.add_upstream(middle.connect());
+
+        // the middle node is not allowed to forward the lookup any more
+        let limited = LookupPacket {
+            request_id: 1,
+            hop_limit: 1,
+            node_id,
+        };
+        assert!(edge.connect().forward(limited).await.unwrap().is_none());
+        assert_eq!(global.requests(), 0);
+
+        let entry = edge.connect().lookup(node_id).await.unwrap().unwrap();
+        assert_eq!(entry.certificate, certificate);
+        assert_eq!(global.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_same_request_id_for_different_nodes() {
+        let (first, second) = (test_util::certificate(), test_util::certificate());
+        let mut store = Store::default();
+        store.insert(first.clone()).unwrap();
+        store.insert(second.clone()).unwrap();
+        let global = Arc::new(TestDirectory {
+            store: Some(store),
+            ..TestDirectory::default()
+        });
+        let edge = Arc::new(TestDirectory::default());
+        edge.proxy.add_upstream(global.connect());
+        let client = edge.connect();
+
+        // lookups of different origins may use the same request ID
+        let packet = |certificate: &RawCertificate| LookupPacket {
+            request_id: 7,
+            hop_limit: 2,
+            node_id: certificate.node_id().unwrap(),
+        };
+        let (a, b) = tokio::join!(
+            client.forward(packet(&first)),
+            client.forward(packet(&second))
+        );
+        assert_eq!(a.unwrap().unwrap().certificate, first);
+        assert_eq!(b.unwrap().unwrap().certificate, second);
+        assert_eq!(global.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_loop_detection() {
+        let certificate = test_util::certificate();
+        let node_id = certificate.node_id().unwrap();
+        // a and b are upstreams of each other, only c knows the node
+        let (a, b) = (
+            Arc::new(TestDirectory::default()),
+            Arc::new(TestDirectory::default()),
+        );
+        let c = TestDirectory::with_certificate(certificate.clone());
+        a.proxy.add_upstream(b.connect());
+        b.proxy.add_upstream(a.connect());
+        b.proxy.add_upstream(c.connect());
+
+        assert!(a
+            .connect()
+            .lookup(NodeId::from([0x42; 32]))
+            .await
+            .unwrap()
+            .is_none());
+        // a -> b -> a is detected, then b asks c
+        assert_eq!((a.requests(), b.requests(), c.requests()), (2, 1, 1));
+
+        let entry = a.connect().lookup(node_id).await.unwrap().unwrap();
+        assert_eq!(entry.certificate, certificate);
+    }
+}
@@ -0,0 +1,480 @@
+//! Certificate store of a directory node
+//!
+//! A stored certificate is only overwritten by a valid certificate of the same node
+//! which is newer, see [`CertificateValidity::is_newer_than`].
+//! Entries expire after the warm table time of the node metadata,
+//! counted from `not_before`, or at the end of the certificate validity.
+//!
+//! A node leaving the network publishes a tombstone, see [`CertificateData::leave`].
+//! Tombstones are stored like every other certificate,
+//! so older certificates of the node are suppressed until the tombstone expires.
+//!
+//! A store can be limited to a single [`Area`], see [`Store::with_area`].
+//! Certificates of nodes outside of the area are verified, but not stored.
+//! If a stored node moves out of the area, its newer certificate is kept as a marker
+//! until it expires, so older certificates of the node in the area are suppressed,
+//! see [`Store::iter_moved`].
+
+use crate::certificate::{
+    CertificateData, CertificateResult, CertificateValidity, Clock, RawCertificate, SystemClock,
+    ValidityPolicy,
+};
+use crate::data::{Area, NodeId};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// Verified certificate of a node
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StoreEntry {
+    pub certificate: RawCertificate,
+    pub data: CertificateData,
+    pub validity: CertificateValidity,
+}
+
+impl StoreEntry {
+    /// Decodes and verifies a certificate using the given validity policy
+    pub fn decode<C: Clock>(
+        certificate: RawCertificate,
+        policy: &ValidityPolicy<C>,
+    ) -> CertificateResult<(NodeId, StoreEntry)> {
+        let (node_id, data) = CertificateData::decode(&certificate, policy)?;
+        let entry = StoreEntry {
+            validity: certificate.validity()?,
+            certificate,
+            data,
+        };
+        Ok((node_id, entry))
+    }
+
+    /// Area the node belongs to
+    pub fn area(&self) -> Option<&Area> {
+        self.data.metadata.area.as_ref()
+    }
+
+    /// Checks if the node left the network
+    pub fn is_tombstone(&self) -> bool {
+        self.data.is_tombstone()
+    }
+
+    /// Point in time at which the entry is removed from the store
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.table_expiry(self.data.metadata.maximum_warm_table_seconds)
+    }
+
+    /// Point in time at which the certificate may no longer be loaded from the cold table
+    pub fn cold_expires_at(&self) -> DateTime<Utc> {
+        self.table_expiry(self.data.metadata.maximum_cold_table_seconds)
+    }
+
+    fn table_expiry(&self, table_seconds: Option<u64>) -> DateTime<Utc> {
+        let not_before = self.validity.not_before;
+        let not_after = self.validity.not_after;
+        // the span of an inverted validity window is negative and never exceeded
+        let span = not_after.signed_duration_since(not_before).num_seconds();
+        table_seconds
+            .and_then(|seconds| i64::try_from(seconds).ok())
+            .filter(|seconds| *seconds < span)
+            .and_then(|seconds| not_before.checked_add_signed(Duration::seconds(seconds)))
+            .unwrap_or(not_after)
+    }
+}
+
+/// Effect of inserting a certificate into the store
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InsertOutcome {
+    /// No certificate of the node was stored before
+    Inserted,
+    /// An older certificate of the node was replaced
+    Replaced,
+    /// The stored certificate is as new or newer, or the certificate already expired
+    Unchanged,
+    /// The node is not part of the area the store is limited to
+    OutOfArea,
+    /// A stored node moved out of the area the store is limited to
+    Removed,
+}
+
+impl InsertOutcome {
+    /// Checks if the store was modified, so the certificate has to be flooded
+    pub fn is_changed(self) -> bool {
+        matches!(
+            self,
+            InsertOutcome::Inserted | InsertOutcome::Replaced | InsertOutcome::Removed
+        )
+    }
+}
+
+/// Certificates of all known nodes, keyed by [`NodeId`]
+#[derive(Debug, Clone)]
+pub struct Store<C = SystemClock> {
+    entries: HashMap<NodeId, StoreEntry>,
+    /// Newer certificates of nodes which moved out of the area
+    moved: HashMap<NodeId, StoreEntry>,
+    policy: ValidityPolicy<C>,
+    area: Option<Area>,
+}
+
+impl Default for Store<SystemClock> {
+    fn default() -> Self {
+        Store::with_policy(ValidityPolicy::default())
+    }
+}
+
+impl<C: Clock> Store<C> {
+    /// Creates an empty store, verifying certificates with the given policy
+    pub fn with_policy(policy: ValidityPolicy<C>) -> Self {
+        Store {
+            entries: HashMap::new(),
+            moved: HashMap::new(),
+            policy,
+            area: None,
+        }
+    }
+
+    /// Limits the store to the certificates of nodes in `area`
+    ///
+    /// Used by hybrid dictionary nodes, which proxy lookups of all other nodes.
+    pub fn with_area(mut self, area: Area) -> Self {
+        self.entries
+            .retain(|_node_id, entry| entry.area() == Some(&area));
+        self.area = Some(area);
+        self
+    }
+
+    /// Area the store is limited to, `None` if it stores all nodes
+    pub fn area(&self) -> Option<&Area> {
+        self.area.as_ref()
+    }
+
+    pub fn policy(&self) -> &ValidityPolicy<C> {
+        &self.policy
+    }
+
+    /// Verifies a certificate and stores it if it is newer than the stored one
+    ///
+    /// Fails if the certificate is invalid, the store is unchanged in that case.
+    /// If the store is limited to an area and a newer certificate moves a stored node
+    /// out of the area, the stored certificate is removed and the newer one is kept
+    /// as a marker until it expires.
+    pub fn insert(&mut self, certificate: RawCertificate) -> CertificateResult<InsertOutcome> {
+        let (node_id, entry) = StoreEntry::decode(certificate, &self.policy)?;
+        let now = self.policy.clock.now();
+        if self.area.is_some() && entry.area() != self.area.as_ref() {
+            let is_newer = |existing: &StoreEntry| entry.validity.is_newer_than(&existing.validity);
+            let moved = self.entries.get(&node_id).is_some_and(is_newer);
+            let newer_marker = self.moved.get(&node_id).is_some_and(is_newer);
+            if moved {
+                self.entries.remove(&node_id);
+            }
+            if (moved || newer_marker) && entry.expires_at() > now {
+                self.moved.insert(node_id, entry);
+            }
+            return Ok(if moved {
+                InsertOutcome::Removed
+            } else {
+                InsertOutcome::OutOfArea
+            });
+        }
+        if entry.expires_at() <= now {
+            return Ok(InsertOutcome::Unchanged);
+        }
+        if let Some(marker) = self.moved.get(&node_id) {
+            if marker.expires_at() > now && !entry.validity.is_newer_than(&marker.validity) {
+                return Ok(InsertOutcome::Unchanged);
+            }
+            self.moved.remove(&node_id);
+        }
+
+        let outcome = match self.entries.get(&node_id) {
+            Some(existing) if existing.expires_at() > now => {
+                if !entry.validity.is_newer_than(&existing.validity) {
+                    return Ok(InsertOutcome::Unchanged);
+                }
+                InsertOutcome::Replaced
+            }
+            Some(_expired) => InsertOutcome::Replaced,
+            None => InsertOutcome::Inserted,
+        };
+        self.entries.insert(node_id, entry);
+        Ok(outcome)
+    }
+
+    /// Returns the stored certificate of a node, if it has not expired yet
+    pub fn get(&self, node_id: &NodeId) -> Option<&StoreEntry> {
+        let now = self.policy.clock.now();
+        self.entries
+            .get(node_id)
+            .filter(|entry| entry.expires_at() > now)
+    }
+
+    pub fn remove(&mut self, node_id: &NodeId) -> Option<StoreEntry> {
+        self.entries.remove(node_id)
+    }
+
+    /// Removes all expired entries
+    ///
+    /// Returns the [`NodeId`]s of the removed entries.
+    pub fn expire(&mut self) -> Vec<NodeId> {
+        let now = self.policy.clock.now();
+        let expired: Vec<NodeId> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at() <= now)
+            .map(|(node_id, _)| *node_id)
+            .collect();
+        for node_id in &expired {
+            self.entries.remove(node_id);
+        }
+        self.moved
+            .retain(|_node_id, entry| entry.expires_at() > now);
+        expired
+    }
+
+    /// Iterates over all entries which have not expired yet
+    pub fn iter(&self) -> impl Iterator<Item = (&NodeId, &StoreEntry)> {
+        let now = self.policy.clock.now();
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| entry.expires_at() > now)
+    }
+
+    /// Returns the certificate which moved a node out of the area, if it has not expired yet
+    pub fn get_moved(&self, node_id: &NodeId) -> Option<&StoreEntry> {
+        let now = self.policy.clock.now();
+        self.moved
+            .get(node_id)
+            .filter(|entry| entry.expires_at() > now)
+    }
+
+    /// Iterates over the certificates which moved nodes out of the area
+    /// and have not expired yet
+    ///
+    /// They are exchanged with the other dictionary nodes of the area,
+    /// so the nodes still storing an older certificate of the node remove it as well.
+    pub fn iter_moved(&self) -> impl Iterator<Item = (&NodeId, &StoreEntry)> {
+        let now = self.policy.clock.now();
+        self.moved
+            .iter()
+            .filter(move |(_, entry)| entry.expires_at() > now)
+    }
+
+    /// Number of stored entries, including expired ones which were not removed yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{
+        CertificateData, CertificateError, NodeMetadata, NodeReachabilityInformation,
+        RawCertificate, ValidityPolicy,
+    };
+    use crate::data::Area;
+    use crate::directory::store::{InsertOutcome, Store, StoreEntry};
+    use crate::test_util::{self, private_key, start, TestClock};
+    use chrono::Duration;
+
+    fn sign(private_key: &[u8], warm_seconds: Option<u64>, minutes: i64) -> RawCertificate {
+        let metadata = NodeMetadata {
+            maximum_warm_table_seconds: warm_seconds,
+            ..NodeMetadata::default()
+        };
+        test_util::sign(private_key, metadata, start() + Duration::minutes(minutes))
+    }
+
+    #[test]
+    fn test_newest_wins() {
+        let clock = TestClock::new(start() + Duration::minutes(30));
+        let mut store = Store::with_policy(ValidityPolicy::new(&clock));
+        let key = private_key();
+        let (older, newer) = (sign(&key, None, 0), sign(&key, None, 10));
+        let node_id = older.node_id().unwrap();
+
+        assert_eq!(
+            store.insert(older.clone()).unwrap(),
+            InsertOutcome::Inserted
+        );
+        assert_eq!(
+            store.insert(older.clone()).unwrap(),
+            InsertOutcome::Unchanged
+        );
+        assert_eq!(
+            store.insert(newer.clone()).unwrap(),
+            InsertOutcome::Replaced
+        );
+        assert_eq!(store.insert(older).unwrap(), InsertOutcome::Unchanged);
+        assert_eq!(store.get(&node_id).unwrap().certificate, newer);
+
+        assert_eq!(
+            store.insert(sign(&private_key(), None, 0)).unwrap(),
+            InsertOutcome::Inserted
+        );
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_reject_invalid() {
+        let clock = TestClock::new(start() + Duration::days(2));
+        let mut store = Store::with_policy(ValidityPolicy::new(&clock));
+
+        assert!(matches!(
+            store.insert(sign(&private_key(), None, 0)),
+            Err(CertificateError::Expired)
+        ));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_expire_warm_table() {
+        let clock = TestClock::new(start());
+        let mut store = Store::with_policy(ValidityPolicy::new(&clock));
+        let (short, long) = (private_key(), private_key());
+        let short_node_id = sign(&short, Some(60), 0).node_id().unwrap();
+
+        assert!(store
+            .insert(sign(&short, Some(60), 0))
+            .unwrap()
+            .is_changed());
+        assert!(store.insert(sign(&long, None, 0)).unwrap().is_changed());
+
+        clock.0.set(start() + Duration::minutes(2));
+        assert!(store.get(&short_node_id).is_none());
+        assert_eq!(store.iter().count(), 1);
+        assert_eq!(store.expire(), vec![short_node_id]);
+        assert_eq!(store.len(), 1);
+
+        // a certificate whose warm table time is over is not stored again
+        assert_eq!(
+            store.insert(sign(&short, Some(60), 0)).unwrap(),
+            InsertOutcome::Unchanged
+        );
+        assert!(store
+            .insert(sign(&short, Some(60), 2))
+            .unwrap()
+            .is_changed());
+    }
+
+    #[test]
+    fn test_table_expiry_of_inverted_window() {
+        let clock = TestClock::new(start());
+        let (_node_id, mut entry) = StoreEntry::decode(
+            sign(&private_key(), Some(100_000_000_000_000_000), 0),
+            &ValidityPolicy::new(&clock),
+        )
+        .unwrap();
+        let not_before = start() + Duration::seconds(100);
+        entry.validity.not_before = not_before;
+        entry.validity.not_after = start() + Duration::seconds(50);
+        assert_eq!(entry.expires_at(), entry.validity.not_after);
+
+        entry.data.metadata.maximum_warm_table_seconds = Some(u64::MAX);
+        assert_eq!(entry.expires_at(), entry.validity.not_after);
+
+        entry.validity.not_after = start() + Duration::days(1);
+        entry.data.metadata.maximum_warm_table_seconds = Some(60);
+        assert_eq!(entry.expires_at(), not_before + Duration::seconds(60));
+        assert_eq!(entry.cold_expires_at(), entry.validity.not_after);
+    }
+
+    #[test]
+    fn test_tombstone() {
+        let clock = TestClock::new(start() + Duration::minutes(2));
+        let mut store = Store::with_policy(ValidityPolicy::new(&clock));
+        let key = private_key();
+        let data = test_util::data(NodeMetadata {
+            maximum_warm_table_seconds: Some(600),
+            ..NodeMetadata::default()
+        });
+        let sign = |data: &CertificateData, minutes| {
+            test_util::sign_data(&key, data, start() + Duration::minutes(minutes))
+        };
+        let reachable = sign(&data, 0);
+        let node_id = reachable.node_id().unwrap();
+        assert!(!data.is_tombstone());
+        assert!(data.leave().is_tombstone());
+        // only reachable through relays, but still part of the network
+        let relayed = CertificateData {
+            reachability: NodeReachabilityInformation::default(),
+            ..data.clone()
+        };
+        assert!(!relayed.is_tombstone());
+
+        store.insert(reachable.clone()).unwrap();
+        assert_eq!(
+            store.insert(sign(&data.leave(), 1)).unwrap(),
+            InsertOutcome::Replaced
+        );
+        assert!(store.get(&node_id).unwrap().is_tombstone());
+        assert_eq!(store.insert(reachable).unwrap(), InsertOutcome::Unchanged);
+        assert!(store.get(&node_id).unwrap().is_tombstone());
+
+        clock.0.set(start() + Duration::minutes(12));
+        assert_eq!(store.expire(), vec![node_id]);
+    }
+
+    #[test]
+    fn test_area() {
+        let clock = TestClock::new(start() + Duration::minutes(30));
+        let eu: Area = "eu".parse().unwrap();
+        let mut store = Store::with_policy(ValidityPolicy::new(&clock)).with_area(eu.clone());
+        assert_eq!(store.area(), Some(&eu));
+        let key = private_key();
+        let sign = |key: &[u8], area: Option<&str>, minutes| {
+            CertificateData {
+                reachability: NodeReachabilityInformation::default(),
+                metadata: NodeMetadata {
+                    area: area.map(|area| area.parse().unwrap()),
+                    ..NodeMetadata::default()
+                },
+            }
+            .sign_with_validity(key, start() + Duration::minutes(minutes), Duration::days(1))
+            .unwrap()
+        };
+        let in_area = sign(&key, Some("eu"), 0);
+        let node_id = in_area.node_id().unwrap();
+
+        assert_eq!(store.insert(in_area).unwrap(), InsertOutcome::Inserted);
+        assert_eq!(store.get(&node_id).unwrap().area(), Some(&eu));
+        assert_eq!(
+            store.insert(sign(&private_key(), None, 5)).unwrap(),
+            InsertOutcome::OutOfArea
+        );
+        assert_eq!(store.len(), 1);
+        assert!(!InsertOutcome::OutOfArea.is_changed());
+
+        // the node moved to another area
+        let moved = sign(&key, Some("us"), 10);
+        assert_eq!(store.insert(moved.clone()).unwrap(), InsertOutcome::Removed);
+        assert!(InsertOutcome::Removed.is_changed());
+        assert!(store.is_empty());
+        assert_eq!(store.insert(moved).unwrap(), InsertOutcome::OutOfArea);
+
+        // a delayed certificate from before the move does not bring the node back
+        assert_eq!(
+            store.insert(sign(&key, Some("eu"), 5)).unwrap(),
+            InsertOutcome::Unchanged
+        );
+        assert!(store.get(&node_id).is_none());
+        assert_eq!(
+            store.insert(sign(&key, Some("us"), 15)).unwrap(),
+            InsertOutcome::OutOfArea
+        );
+        assert_eq!(
+            store.insert(sign(&key, Some("eu"), 12)).unwrap(),
+            InsertOutcome::Unchanged
+        );
+
+        // the node moved back
+        assert_eq!(
+            store.insert(sign(&key, Some("eu"), 20)).unwrap(),
+            InsertOutcome::Inserted
+        );
+        assert_eq!(store.get(&node_id).unwrap().area(), Some(&eu));
+    }
+}
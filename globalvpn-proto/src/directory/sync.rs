@@ -0,0 +1,288 @@
+//! Warm table synchronization
+//!
+//! A node sends the digest of its store, see [`digest`].
+//! The directory node answers with only the certificates which are missing or outdated
+//! in the digest, so a reconnecting node does not download the whole directory again.
+//! The packets are described in [`crate::protocol::sync`].
+//!
+//! Certificates which moved a node out of the area of the store are exchanged as well,
+//! see [`Store::iter_moved`].
+//!
+//! A digest may list at most [`MAX_SYNC_DIGEST_NODES`] nodes,
+//! so a peer can not make the directory node keep an unbounded state.
+
+use crate::certificate::Clock;
+use crate::data::NodeId;
+use crate::directory::store::Store;
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::Frame;
+use crate::protocol::sync::{DigestEntry, SyncDigestPacket, SyncDonePacket};
+use crate::protocol::update::UpdatePacket;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+/// Maximum number of nodes a single digest may list
+pub const MAX_SYNC_DIGEST_NODES: u32 = 1 << 20;
+
+/// SYNC_DIGEST frames describing all entries of the store,
+/// including tombstones and nodes which moved out of the area
+pub fn digest<C: Clock>(store: &Store<C>) -> Vec<Frame> {
+    let entries = store
+        .iter()
+        .chain(store.iter_moved())
+        .map(|(node_id, entry)| DigestEntry {
+            node_id: *node_id,
+            version: entry.validity.version(),
+        })
+        .collect();
+    SyncDigestPacket::split(entries)
+        .into_iter()
+        .map(Frame::SyncDigest)
+        .collect()
+}
+
+/// Answers the digest of a single peer
+///
+/// The digest may be split over multiple packets,
+/// so the state has to be kept until the last packet was received.
+#[derive(Debug, Default)]
+pub struct WarmSync {
+    seen: HashSet<NodeId>,
+    updates: u32,
+}
+
+impl WarmSync {
+    pub fn new() -> Self {
+        WarmSync::default()
+    }
+
+    /// Handles a SYNC_DIGEST packet of the peer
+    ///
+    /// Returns UPDATE frames for the certificates which are newer than the ones in the digest.
+    /// After the last packet of the digest, all stored certificates which were not listed
+    /// are returned as well, followed by a SYNC_DONE frame.
+    ///
+    /// Fails if the digest lists more than [`MAX_SYNC_DIGEST_NODES`] nodes,
+    /// the state is reset in that case.
+    pub fn receive_digest<C: Clock>(
+        &mut self,
+        store: &Store<C>,
+        packet: SyncDigestPacket,
+    ) -> ProtocolResult<Vec<Frame>> {
+        let mut frames = Vec::new();
+        for peer_entry in packet.entries {
+            if self.seen.insert(peer_entry.node_id)
+                && self.seen.len() > MAX_SYNC_DIGEST_NODES as usize
+            {
+                *self = WarmSync::new();
+                return Err(ProtocolError::DigestTooLarge {
+                    max_entries: MAX_SYNC_DIGEST_NODES,
+                });
+            }
+            let entry = store
+                .get(&peer_entry.node_id)
+                .or_else(|| store.get_moved(&peer_entry.node_id));
+            if let Some(entry) = entry {
+                if entry.validity.version() > peer_entry.version {
+                    frames.push(Frame::Update(UpdatePacket::new(entry.certificate.clone())));
+                }
+            }
+        }
+
+        if packet.last {
+            // nodes which moved out of the area are only of interest to peers listing them
+            for (node_id, entry) in store.iter() {
+                if !self.seen.contains(node_id) {
+                    frames.push(Frame::Update(UpdatePacket::new(entry.certificate.clone())));
+                }
+            }
+        }
+
+        // the counter saturates for stores with more than u32::MAX entries
+        let updates = u32::try_from(frames.len()).unwrap_or(u32::MAX);
+        self.updates = self.updates.saturating_add(updates);
+        if packet.last {
+            frames.push(Frame::SyncDone(SyncDonePacket {
+                updates: self.updates,
+            }));
+            *self = WarmSync::new();
+        }
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{CertificateVersion, NodeMetadata, RawCertificate};
+    use crate::data::NodeId;
+    use crate::directory::flood::Flooding;
+    use crate::directory::store::{InsertOutcome, Store};
+    use crate::directory::sync::{digest, WarmSync, MAX_SYNC_DIGEST_NODES};
+    use crate::protocol::error::ProtocolError;
+    use crate::protocol::frame::Frame;
+    use crate::protocol::sync::{DigestEntry, SyncDigestPacket, SyncDonePacket};
+    use crate::test_util::{private_key, sign, sign_from_now};
+    use chrono::{Duration, Utc};
+
+    /// Sends the digest of `node` to `directory` and applies the answer
+    ///
+    /// Returns the number of transferred certificates.
+    fn sync(node: &mut Flooding, directory: &Flooding) -> u32 {
+        let directory_id = NodeId::from([0xdd; 32]);
+        let mut warm_sync = WarmSync::new();
+        let mut updates = 0;
+        let mut done = None;
+        for frame in digest(node.store()) {
+            let packet = match frame {
+                Frame::SyncDigest(packet) => packet,
+                other => panic!("unexpected frame {:?}", other),
+            };
+            for response in warm_sync.receive_digest(directory.store(), packet).unwrap() {
+                match response {
+                    Frame::Update(packet) => {
+                        assert!(node.receive(&directory_id, packet).unwrap().is_changed());
+                        updates += 1;
+                    }
+                    Frame::SyncDone(packet) => done = Some(packet),
+                    other => panic!("unexpected frame {:?}", other),
+                }
+            }
+        }
+        assert_eq!(done, Some(SyncDonePacket { updates }));
+        updates
+    }
+
+    fn certificates(flooding: &Flooding) -> Vec<RawCertificate> {
+        let mut certificates: Vec<_> = flooding
+            .store()
+            .iter()
+            .map(|(_, entry)| entry.certificate.clone())
+            .collect();
+        certificates.sort();
+        certificates
+    }
+
+    #[test]
+    fn test_transfer_missing_and_newer() {
+        let (mut a, mut b) = (Flooding::default(), Flooding::default());
+        let x = private_key();
+        let (x_old, x_new) = (sign_from_now(&x, 0), sign_from_now(&x, 60));
+        let y = sign_from_now(&private_key(), 0);
+        a.publish(x_old).unwrap();
+        a.publish(y.clone()).unwrap();
+        a.publish(sign_from_now(&private_key(), 0)).unwrap();
+        b.publish(x_new).unwrap();
+        b.publish(y).unwrap();
+        b.publish(sign_from_now(&private_key(), 0)).unwrap();
+
+        // newer x and the missing certificate, but not y
+        assert_eq!(sync(&mut a, &b), 2);
+        // only the certificate b is missing
+        assert_eq!(sync(&mut b, &a), 1);
+        assert_eq!(certificates(&a), certificates(&b));
+        assert_eq!(certificates(&a).len(), 4);
+
+        // nothing is transferred again
+        assert_eq!(sync(&mut a, &b), 0);
+        assert_eq!(sync(&mut b, &a), 0);
+    }
+
+    #[test]
+    fn test_node_moved_out_of_area() {
+        let area_store = || Store::default().with_area("eu".parse().unwrap());
+        let (mut a, mut b) = (Flooding::new(area_store()), Flooding::new(area_store()));
+        let x = private_key();
+        let sign = |area: &str, seconds| {
+            let metadata = NodeMetadata {
+                area: Some(area.parse().unwrap()),
+                ..NodeMetadata::default()
+            };
+            sign(&x, metadata, Utc::now() + Duration::seconds(seconds))
+        };
+        let (x_old, x_moved) = (sign("eu", 0), sign("us", 60));
+        a.publish(x_old.clone()).unwrap();
+        b.publish(x_old.clone()).unwrap();
+        assert_eq!(b.publish(x_moved).unwrap(), InsertOutcome::Removed);
+        assert!(b.store().is_empty());
+
+        // a still lists x, the directory node answers with the certificate of the move
+        assert_eq!(sync(&mut a, &b), 1);
+        assert!(a.store().is_empty());
+        assert_eq!(
+            a.publish(x_old).unwrap(),
+            InsertOutcome::Unchanged,
+            "older certificate in the area"
+        );
+
+        // both list the move, and a fresh node of the area is not sent the move
+        assert_eq!(sync(&mut a, &b), 0);
+        assert_eq!(sync(&mut b, &a), 0);
+        assert_eq!(sync(&mut Flooding::new(area_store()), &a), 0);
+    }
+
+    #[test]
+    fn test_split_digest() {
+        let (mut a, mut b) = (Flooding::default(), Flooding::default());
+        let known = sign_from_now(&private_key(), 0);
+        let missing = sign_from_now(&private_key(), 0);
+        a.publish(known.clone()).unwrap();
+        b.publish(known).unwrap();
+        b.publish(missing.clone()).unwrap();
+
+        let mut packet = match digest(a.store()).pop() {
+            Some(Frame::SyncDigest(packet)) => packet,
+            other => panic!("unexpected frame {:?}", other),
+        };
+        packet.last = false;
+        let mut warm_sync = WarmSync::new();
+        assert!(warm_sync
+            .receive_digest(b.store(), packet)
+            .unwrap()
+            .is_empty());
+
+        let frames = warm_sync
+            .receive_digest(b.store(), SyncDigestPacket::split(Vec::new())[0].clone())
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(matches!(&frames[0], Frame::Update(packet) if packet.certificate == missing));
+        assert_eq!(frames[1], Frame::SyncDone(SyncDonePacket { updates: 1 }));
+    }
+
+    #[test]
+    fn test_reject_oversized_digest() {
+        let directory = Flooding::default();
+        let version = CertificateVersion {
+            not_before: Utc::now(),
+            sequence_number: 0,
+        };
+        let entries: Vec<_> = (0..=MAX_SYNC_DIGEST_NODES)
+            .map(|index| {
+                let mut node_id = [0u8; 32];
+                node_id[..4].copy_from_slice(&index.to_be_bytes());
+                DigestEntry {
+                    node_id: NodeId::from(node_id),
+                    version,
+                }
+            })
+            .collect();
+        let (allowed, exceeding) = entries.split_at(MAX_SYNC_DIGEST_NODES as usize);
+
+        let mut warm_sync = WarmSync::new();
+        let packet = |entries: &[DigestEntry]| SyncDigestPacket {
+            last: false,
+            entries: entries.to_vec(),
+        };
+        // listing a node twice does not count
+        assert!(warm_sync
+            .receive_digest(directory.store(), packet(allowed))
+            .is_ok());
+        assert!(warm_sync
+            .receive_digest(directory.store(), packet(&allowed[..1]))
+            .is_ok());
+        assert!(matches!(
+            warm_sync.receive_digest(directory.store(), packet(exceeding)),
+            Err(ProtocolError::DigestTooLarge { max_entries }) if max_entries == MAX_SYNC_DIGEST_NODES
+        ));
+        assert!(warm_sync.seen.is_empty());
+    }
+}
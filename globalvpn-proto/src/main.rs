@@ -18,6 +18,7 @@ use crate::certificate::{
     CertificateData, NodeIpReachability, NodeMetadata, NodeProxyReachability,
     NodeReachabilityInformation,
 };
+use crate::data::NodeId;
 use log::{info, LevelFilter};
 use ring::rand::SystemRandom;
 use std::collections::BTreeSet;
@@ -26,9 +27,16 @@ use std::fs::File;
 use std::io::Write;
 
 pub mod certificate;
-mod data;
+pub mod data;
+pub mod dht;
+pub mod directory;
+mod file;
+pub mod node;
 mod prelude;
-mod protocol;
+pub mod protocol;
+pub mod session;
+#[cfg(test)]
+mod test_util;
 
 fn main() -> anyhow::Result<()> {
     env_logger::builder().filter_level(LevelFilter::Info).init();
@@ -47,7 +55,7 @@ fn main() -> anyhow::Result<()> {
         .into_iter()
         .collect(),
         proxy_reachability: vec![NodeProxyReachability {
-            proxy_address: vec![123, 34, 34, 212, 43, 93],
+            proxy_address: NodeId::from([123; 32]),
             proxy_reachability: BTreeSet::new(),
         }]
         .into_iter()
@@ -57,6 +65,9 @@ fn main() -> anyhow::Result<()> {
     let metadata = NodeMetadata {
         maximum_warm_table_seconds: Some(2600),
         maximum_cold_table_seconds: None,
+        sequence_number: None,
+        area: None,
+        tombstone: false,
     };
 
     let certificate_data = CertificateData {
@@ -75,10 +86,6 @@ fn main() -> anyhow::Result<()> {
 
     println!("{}", encoded.pem());
 
-    let (_rem, cer) = x509_parser::parse_x509_certificate(encoded.der())?;
-    let issuer_pk = &cer.tbs_certificate.subject_pki;
-    cer.verify_signature(Some(issuer_pk))?;
-
     info!("Hello, world!");
     Ok(())
 }
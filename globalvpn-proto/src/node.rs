@@ -0,0 +1,374 @@
+//! Local node
+//!
+//! A node publishes its certificate to the connected directory nodes,
+//! which flood it to all other directory nodes.
+//! Frames received from directory nodes are not used by the node and are dropped,
+//! so a directory node flooding updates can not stall the connection.
+//! A node which leaves the network publishes a tombstone, see [`CertificateData::leave`],
+//! before closing its connections.
+//!
+//! Every signed certificate gets the next number of the [`SequenceCounter`],
+//! so certificates signed in the same second can still be ordered.
+//! The `not_before` time of a certificate is never earlier than the one of the
+//! previous certificate, which is recorded by the [`SequenceCounter`],
+//! so a clock stepping backwards does not make a new certificate look older,
+//! even across restarts.
+
+use crate::certificate::{
+    CertificateData, CertificateError, Clock, RawCertificate, SequenceCounter, SystemClock,
+};
+use crate::data::NodeId;
+use crate::protocol::connection::Connection;
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::Frame;
+use crate::protocol::update::UpdatePacket;
+use crate::session::Identity;
+use log::debug;
+use std::io;
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+pub type NodeResult<T> = Result<T, NodeError>;
+
+#[derive(Error, Debug)]
+pub enum NodeError {
+    #[error("Certificate error: {0}")]
+    Certificate(#[from] CertificateError),
+    #[error("Protocol error: {0}")]
+    Protocol(#[from] ProtocolError),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Local node with its connections to directory nodes
+#[derive(Debug)]
+pub struct Node<C = SystemClock> {
+    clock: C,
+    identity: Identity,
+    data: CertificateData,
+    certificate: Option<RawCertificate>,
+    sequence: SequenceCounter,
+    directories: Vec<Directory>,
+}
+
+/// Connection to a directory node, whose received frames are drained by a task
+#[derive(Debug)]
+struct Directory {
+    sender: mpsc::Sender<Frame>,
+    close: oneshot::Sender<()>,
+    task: JoinHandle<ProtocolResult<()>>,
+}
+
+impl Directory {
+    fn spawn(mut connection: Connection) -> Self {
+        let sender = connection.sender();
+        let (close, mut closing) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    frame = connection.recv() => match frame {
+                        Some(frame) => debug!("dropping frame from directory node: {:?}", frame),
+                        None => break,
+                    },
+                    _ = &mut closing => break,
+                }
+            }
+            connection.close().await
+        });
+        Directory {
+            sender,
+            close,
+            task,
+        }
+    }
+
+    /// Closes the connection after all queued frames are sent
+    async fn close(self) -> NodeResult<()> {
+        // the connection is only closed once all senders are dropped
+        drop(self.sender);
+        // the task may already have ended if the directory node closed the connection
+        let _ = self.close.send(());
+        self.task
+            .await
+            .unwrap_or(Err(ProtocolError::ConnectionClosed))
+            .map_err(NodeError::from)
+    }
+}
+
+impl Node<SystemClock> {
+    pub fn new(identity: Identity, data: CertificateData) -> Self {
+        Node {
+            clock: SystemClock,
+            identity,
+            data,
+            certificate: None,
+            sequence: SequenceCounter::default(),
+            directories: Vec::new(),
+        }
+    }
+}
+
+impl<C: Clock> Node<C> {
+    /// Uses the given clock for the `not_before` time of signed certificates
+    pub fn with_clock<D: Clock>(self, clock: D) -> Node<D> {
+        Node {
+            clock,
+            identity: self.identity,
+            data: self.data,
+            certificate: self.certificate,
+            sequence: self.sequence,
+            directories: self.directories,
+        }
+    }
+
+    /// Uses the given counter for the sequence numbers of signed certificates
+    ///
+    /// Without a file backed counter, certificates signed after a restart
+    /// are only ordered by their `not_before` time, which goes backwards
+    /// if the clock is behind the last published certificate.
+    pub fn with_sequence_counter(mut self, sequence: SequenceCounter) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.identity.node_id()
+    }
+
+    pub fn data(&self) -> &CertificateData {
+        &self.data
+    }
+
+    /// Last published certificate
+    pub fn certificate(&self) -> Option<&RawCertificate> {
+        self.certificate.as_ref()
+    }
+
+    /// Adds a connection to a directory node, which receives all published certificates
+    ///
+    /// Frames received from the directory node are drained by a spawned task.
+    pub fn add_directory(&mut self, connection: Connection) {
+        self.directories.push(Directory::spawn(connection));
+    }
+
+    /// Signs the certificate data and sends it to all connected directory nodes
+    pub async fn publish(&mut self) -> NodeResult<RawCertificate> {
+        let certificate = self.sign(&self.data.clone())?;
+        self.send(&certificate).await?;
+        Ok(certificate)
+    }
+
+    /// Leaves the network
+    ///
+    /// Sends a tombstone, see [`CertificateData::leave`], to all connected directory nodes
+    /// and closes the connections after it was sent.
+    pub async fn shutdown(mut self) -> NodeResult<()> {
+        let tombstone = self.sign(&self.data.leave())?;
+        // the connections are closed even if sending failed, the first error is returned
+        let mut result = self.send(&tombstone).await;
+        for directory in self.directories {
+            result = result.and(directory.close().await);
+        }
+        result
+    }
+
+    /// Signs certificate data with the next sequence number,
+    /// so that it is newer than the last published certificate
+    ///
+    /// If the clock went backwards, the `not_before` time of the last published
+    /// certificate is used again.
+    fn sign(&mut self, data: &CertificateData) -> NodeResult<RawCertificate> {
+        let mut data = data.clone();
+        let validity = data.metadata.validity()?;
+        let now = self.clock.now();
+        let not_before = self.sequence.not_before().map_or(now, |last| last.max(now));
+        data.metadata.sequence_number = Some(self.sequence.increment_at(not_before)?);
+        let certificate =
+            data.sign_with_validity(&self.identity.to_pkcs8_der(), not_before, validity)?;
+        self.certificate = Some(certificate.clone());
+        Ok(certificate)
+    }
+
+    async fn send(&self, certificate: &RawCertificate) -> NodeResult<()> {
+        for directory in &self.directories {
+            let frame = Frame::Update(UpdatePacket::new(certificate.clone()));
+            directory
+                .sender
+                .send(frame)
+                .await
+                .map_err(|_err| ProtocolError::ConnectionClosed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{CertificateData, NodeMetadata, SequenceCounter, ValidityPolicy};
+    use crate::directory::{Flooding, InsertOutcome, Store};
+    use crate::node::Node;
+    use crate::protocol::connection::{Connection, ConnectionConfig};
+    use crate::protocol::frame::Frame;
+    use crate::protocol::update::UpdatePacket;
+    use crate::session::Identity;
+    use crate::test_util::{self, TestClock};
+    use chrono::Duration;
+    use std::fs;
+
+    fn data() -> CertificateData {
+        test_util::data(NodeMetadata::default())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_publishes_tombstone() {
+        let identity = Identity::generate();
+        let node_id = identity.node_id();
+        let mut node = Node::new(identity, data());
+
+        let mut directories = Vec::new();
+        for _ in 0..2 {
+            let (a, b) = tokio::io::duplex(4096);
+            node.add_directory(Connection::spawn(a, ConnectionConfig::default()));
+            directories.push(Connection::spawn(b, ConnectionConfig::default()));
+        }
+
+        let certificate = node.publish().await.unwrap();
+        assert_eq!(certificate.node_id().unwrap(), node_id);
+        node.shutdown().await.unwrap();
+
+        for mut directory in directories {
+            let mut flooding = Flooding::default();
+            let mut outcomes = Vec::new();
+            while let Some(frame) = directory.recv().await {
+                match frame {
+                    Frame::Update(packet) => outcomes.push(flooding.publish(packet.certificate)),
+                    other => panic!("unexpected frame {:?}", other),
+                }
+            }
+            let outcomes: Vec<_> = outcomes.into_iter().map(Result::unwrap).collect();
+            assert_eq!(
+                outcomes,
+                vec![InsertOutcome::Inserted, InsertOutcome::Replaced]
+            );
+            assert!(flooding.store().get(&node_id).unwrap().is_tombstone());
+            directory.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_frames_from_directory() {
+        let mut node = Node::new(Identity::generate(), data());
+        // large enough for all frames, so the directory node is never blocked
+        let (a, b) = tokio::io::duplex(1 << 20);
+        node.add_directory(Connection::spawn(a, ConnectionConfig::default()));
+        let mut directory = Connection::spawn(b, ConnectionConfig::default());
+
+        // more frames than fit into the inbound queue of the node
+        let update = Frame::Update(UpdatePacket::new(test_util::certificate()));
+        let sender = directory.sender();
+        tokio::spawn(async move {
+            for _ in 0..256 {
+                let _ = sender.send(update.clone()).await;
+            }
+        });
+        // the node still sends keepalives, so the directory node does not time out
+        tokio::time::sleep(std::time::Duration::from_secs(300)).await;
+
+        let certificate = node.publish().await.unwrap();
+        assert_eq!(
+            directory.recv().await,
+            Some(Frame::Update(UpdatePacket::new(certificate)))
+        );
+        node.shutdown().await.unwrap();
+        directory.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sequence_number_survives_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "globalvpn-node-sequence-{}.txt",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let identity = Identity::generate();
+        let node = || {
+            Node::new(identity.clone(), data())
+                .with_sequence_counter(SequenceCounter::open(&path).unwrap())
+        };
+
+        let mut store = Store::default();
+        let first = node().publish().await.unwrap();
+        // signed in the same second after a restart
+        let second = node().publish().await.unwrap();
+        assert_eq!(first.validity().unwrap().sequence_number, 1);
+        assert_eq!(second.validity().unwrap().sequence_number, 2);
+        assert_eq!(
+            store.insert(first.clone()).unwrap(),
+            InsertOutcome::Inserted
+        );
+        assert_eq!(store.insert(second).unwrap(), InsertOutcome::Replaced);
+        assert_eq!(store.insert(first).unwrap(), InsertOutcome::Unchanged);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clock_steps_backwards() {
+        let start = test_util::start();
+        let clock = TestClock::new(start);
+        let mut node = Node::new(Identity::generate(), data()).with_clock(&clock);
+        let mut store = Store::with_policy(ValidityPolicy::new(&clock));
+
+        let first = node.publish().await.unwrap();
+        clock.0.set(start - Duration::hours(1));
+        let second = node.publish().await.unwrap();
+        let (first_validity, second_validity) =
+            (first.validity().unwrap(), second.validity().unwrap());
+        assert_eq!(second_validity.not_before, first_validity.not_before);
+        assert!(second_validity.version() > first_validity.version());
+
+        clock.0.set(start);
+        assert_eq!(store.insert(first).unwrap(), InsertOutcome::Inserted);
+        assert_eq!(store.insert(second).unwrap(), InsertOutcome::Replaced);
+
+        // the clock is used again once it passed the last certificate
+        clock.0.set(start + Duration::hours(1));
+        let third = node.publish().await.unwrap();
+        assert_eq!(
+            third.validity().unwrap().not_before,
+            start + Duration::hours(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clock_steps_backwards_across_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "globalvpn-node-not-before-{}.txt",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let start = test_util::start();
+        let clock = TestClock::new(start);
+        let identity = Identity::generate();
+        let node = || {
+            Node::new(identity.clone(), data())
+                .with_sequence_counter(SequenceCounter::open(&path).unwrap())
+                .with_clock(&clock)
+        };
+        let mut store = Store::with_policy(ValidityPolicy::new(&clock));
+
+        let first = node().publish().await.unwrap();
+        // restarted with the clock an hour behind
+        clock.0.set(start - Duration::hours(1));
+        let second = node().publish().await.unwrap();
+        assert_eq!(
+            second.validity().unwrap().not_before,
+            first.validity().unwrap().not_before
+        );
+
+        clock.0.set(start);
+        assert_eq!(store.insert(first).unwrap(), InsertOutcome::Inserted);
+        assert_eq!(store.insert(second).unwrap(), InsertOutcome::Replaced);
+        fs::remove_file(&path).unwrap();
+    }
+}
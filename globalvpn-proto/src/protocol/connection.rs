@@ -0,0 +1,313 @@
+//! Connection handling
+//!
+//! Each connection is driven by its own task which reads and writes frames,
+//! sends KEEPALIVE packets, detects dead peers and dispatches CUSTOM packets.
+
+use crate::protocol::custom::CustomPacketRegistry;
+use crate::protocol::error::{ErrorPacket, ProtocolError, ProtocolResult};
+use crate::protocol::frame::{Frame, FrameCodec};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use log::debug;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+use tokio_util::codec::Framed;
+
+/// Number of frames buffered in each direction
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Settings for sending KEEPALIVE packets and detecting dead peers
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct KeepaliveConfig {
+    /// Interval in which KEEPALIVE packets are sent
+    pub interval: Duration,
+    /// Number of intervals without any received packet after which the peer is considered dead
+    pub max_missed_intervals: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        KeepaliveConfig {
+            interval: Duration::from_secs(15),
+            max_missed_intervals: 3,
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    /// Time without any received packet after which the peer is considered dead
+    pub fn timeout(&self) -> Duration {
+        self.interval * self.max_missed_intervals
+    }
+}
+
+/// Settings of a connection
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionConfig {
+    pub keepalive: KeepaliveConfig,
+    /// Handlers for received CUSTOM packets
+    pub custom_packets: Arc<CustomPacketRegistry>,
+}
+
+/// Handle to a connection driven by a background task
+///
+/// KEEPALIVE and CUSTOM packets are handled by the connection
+/// and never returned by [`Connection::recv`].
+#[derive(Debug)]
+pub struct Connection {
+    outbound: mpsc::Sender<Frame>,
+    inbound: mpsc::Receiver<Frame>,
+    task: JoinHandle<ProtocolResult<()>>,
+}
+
+impl Connection {
+    /// Spawns the task driving an unencrypted connection
+    pub fn spawn<T>(io: T, config: ConnectionConfig) -> Connection
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Connection::spawn_transport(Framed::new(io, FrameCodec::new()), config)
+    }
+
+    /// Spawns the task driving a connection over an arbitrary frame transport
+    ///
+    /// Used to run a connection over a [`crate::session::SecureStream`].
+    pub fn spawn_transport<S>(transport: S, config: ConnectionConfig) -> Connection
+    where
+        S: Stream<Item = ProtocolResult<Frame>>
+            + Sink<Frame, Error = ProtocolError>
+            + Send
+            + Unpin
+            + 'static,
+    {
+        let (outbound, outbound_receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        let (inbound_sender, inbound) = mpsc::channel(CHANNEL_CAPACITY);
+        let task = tokio::spawn(drive(transport, config, inbound_sender, outbound_receiver));
+        Connection {
+            outbound,
+            inbound,
+            task,
+        }
+    }
+
+    /// Queues a frame to be sent to the peer
+    pub async fn send(&self, frame: Frame) -> ProtocolResult<()> {
+        self.outbound
+            .send(frame)
+            .await
+            .map_err(|_err| ProtocolError::ConnectionClosed)
+    }
+
+    /// Receives the next frame, returns `None` if the connection is closed
+    pub async fn recv(&mut self) -> Option<Frame> {
+        self.inbound.recv().await
+    }
+
+    /// A sender which can be used to send frames from other tasks
+    pub fn sender(&self) -> mpsc::Sender<Frame> {
+        self.outbound.clone()
+    }
+
+    /// Closes the connection after all queued frames are sent
+    ///
+    /// Returns the error which terminated the connection, if any.
+    pub async fn close(self) -> ProtocolResult<()> {
+        drop(self.outbound);
+        drop(self.inbound);
+        self.task
+            .await
+            .unwrap_or(Err(ProtocolError::ConnectionClosed))
+    }
+}
+
+async fn drive<S>(
+    mut framed: S,
+    config: ConnectionConfig,
+    inbound: mpsc::Sender<Frame>,
+    mut outbound: mpsc::Receiver<Frame>,
+) -> ProtocolResult<()>
+where
+    S: Stream<Item = ProtocolResult<Frame>> + Sink<Frame, Error = ProtocolError> + Unpin,
+{
+    let result = run(&mut framed, config, inbound, &mut outbound).await;
+    if let Err(err) = &result {
+        debug!("closing connection: {}", err);
+        if let Ok(packet) = ErrorPacket::try_from(err) {
+            // the peer may already be gone, so errors are ignored
+            let _ = framed.send(Frame::Error(packet)).await;
+        }
+    }
+    result
+}
+
+async fn run<S>(
+    framed: &mut S,
+    config: ConnectionConfig,
+    inbound: mpsc::Sender<Frame>,
+    outbound: &mut mpsc::Receiver<Frame>,
+) -> ProtocolResult<()>
+where
+    S: Stream<Item = ProtocolResult<Frame>> + Sink<Frame, Error = ProtocolError> + Unpin,
+{
+    let keepalive = config.keepalive;
+    let mut ticker = interval_at(Instant::now() + keepalive.interval, keepalive.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_received = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if last_received.elapsed() >= keepalive.timeout() {
+                    return Err(ProtocolError::KeepaliveTimeout);
+                }
+                framed.send(Frame::Keepalive).await?;
+            }
+            frame = framed.next() => {
+                let frame = match frame {
+                    Some(frame) => frame?,
+                    None => return Ok(()),
+                };
+                last_received = Instant::now();
+                match frame {
+                    Frame::Keepalive => {}
+                    Frame::Custom(packet) => {
+                        if let Some(response) = config.custom_packets.dispatch(packet) {
+                            framed.send(response).await?;
+                        }
+                    }
+                    frame => {
+                        if inbound.send(frame).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            frame = outbound.recv() => match frame {
+                Some(frame) => framed.send(frame).await?,
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::connection::{Connection, ConnectionConfig, KeepaliveConfig};
+    use crate::protocol::custom::{CustomPacket, CustomPacketRegistry};
+    use crate::protocol::error::{ErrorPacket, ProtocolError};
+    use crate::protocol::frame::{Frame, FrameCodec};
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_util::codec::Framed;
+
+    fn keepalive() -> KeepaliveConfig {
+        KeepaliveConfig {
+            interval: Duration::from_secs(1),
+            max_missed_intervals: 3,
+        }
+    }
+
+    fn config() -> ConnectionConfig {
+        ConnectionConfig {
+            keepalive: keepalive(),
+            ..ConnectionConfig::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exchange_frames() {
+        let (a, b) = tokio::io::duplex(1024);
+        let a = Connection::spawn(a, config());
+        let mut b = Connection::spawn(b, config());
+
+        let frame = Frame::Error(ErrorPacket::new(0xff, 0xff, Bytes::from_static(b"first")));
+        a.send(frame.clone()).await.unwrap();
+        assert_eq!(b.recv().await, Some(frame));
+
+        // keepalives keep the connection open and are not returned
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let frame = Frame::Error(ErrorPacket::new(
+            0xff,
+            0xff,
+            Bytes::from_static(b"still alive"),
+        ));
+        a.send(frame.clone()).await.unwrap();
+        assert_eq!(b.recv().await, Some(frame));
+
+        a.close().await.unwrap();
+        assert_eq!(b.recv().await, None);
+        b.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_timeout() {
+        let (a, b) = tokio::io::duplex(1024);
+        let mut a = Connection::spawn(a, config());
+        // the peer never answers
+        let mut b = Framed::new(b, FrameCodec::new());
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(a.recv().await, None);
+        assert!(start.elapsed() >= keepalive().timeout());
+        assert!(matches!(
+            a.close().await,
+            Err(ProtocolError::KeepaliveTimeout)
+        ));
+
+        let mut keepalives = 0;
+        while let Some(frame) = b.next().await {
+            match frame.unwrap() {
+                Frame::Keepalive => keepalives += 1,
+                Frame::Error(packet) => {
+                    assert!(matches!(
+                        ProtocolError::from(packet),
+                        ProtocolError::KeepaliveTimeout
+                    ));
+                    break;
+                }
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+        assert_eq!(keepalives, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dispatch_custom_packets() {
+        let registry = Arc::new(CustomPacketRegistry::new());
+        registry.register(1, 1, |packet: CustomPacket| {
+            Some(Frame::Custom(CustomPacket::new(1, 2, packet.data)))
+        });
+        let (a, b) = tokio::io::duplex(1024);
+        let _a = Connection::spawn(
+            a,
+            ConnectionConfig {
+                keepalive: keepalive(),
+                custom_packets: registry,
+            },
+        );
+        let mut b = Connection::spawn(b, config());
+
+        b.send(Frame::Custom(CustomPacket::new(1, 1, vec![42])))
+            .await
+            .unwrap();
+        b.send(Frame::Custom(CustomPacket::new(1, 3, vec![42])))
+            .await
+            .unwrap();
+
+        // the custom response to subtype 1 is consumed by the registry of b,
+        // so only the error about subtype 3 reaches the application
+        let expected_error = ErrorPacket::try_from(&ProtocolError::UnknownCustomPacket {
+            vendor_id: 1,
+            subtype: 3,
+        })
+        .unwrap();
+        assert_eq!(b.recv().await, Some(Frame::Error(expected_error)));
+    }
+}
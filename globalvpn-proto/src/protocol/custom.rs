@@ -0,0 +1,206 @@
+//! CUSTOM packet
+//!
+//! Allows implementation specific extensions of the protocol.
+//!
+//! | Type  | Name      |
+//! | ----- | --------- |
+//! | u32   | Vendor ID |
+//! | u16   | subtype   |
+//! | bytes | Data      |
+//!
+//! Handlers for custom packets are registered in a [`CustomPacketRegistry`].
+//! Custom packets without a registered handler are answered with an ERROR packet.
+
+use crate::protocol::error::{ErrorPacket, ProtocolError, ProtocolResult};
+use crate::protocol::frame::{Frame, PacketPayload, PacketType};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CustomPacket {
+    pub vendor_id: u32,
+    pub subtype: u16,
+    pub data: Bytes,
+}
+
+impl CustomPacket {
+    pub fn new(vendor_id: u32, subtype: u16, data: impl Into<Bytes>) -> Self {
+        CustomPacket {
+            vendor_id,
+            subtype,
+            data: data.into(),
+        }
+    }
+}
+
+impl PacketPayload for CustomPacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u32(self.vendor_id);
+        dst.put_u16(self.subtype);
+        dst.put_slice(&self.data);
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        if payload.remaining() < 6 {
+            return Err(ProtocolError::MalformedPacket(PacketType::Custom));
+        }
+        let vendor_id = payload.get_u32();
+        let subtype = payload.get_u16();
+        Ok(CustomPacket {
+            vendor_id,
+            subtype,
+            data: payload,
+        })
+    }
+}
+
+/// Handler for a single vendor ID and subtype
+///
+/// The returned frame is send back to the peer.
+pub trait CustomPacketHandler: Send + Sync {
+    fn handle(&self, packet: CustomPacket) -> Option<Frame>;
+}
+
+impl<F> CustomPacketHandler for F
+where
+    F: Fn(CustomPacket) -> Option<Frame> + Send + Sync,
+{
+    fn handle(&self, packet: CustomPacket) -> Option<Frame> {
+        self(packet)
+    }
+}
+
+/// Registry of handlers for custom packets
+#[derive(Default)]
+pub struct CustomPacketRegistry {
+    handlers: RwLock<HashMap<(u32, u16), Arc<dyn CustomPacketHandler>>>,
+}
+
+impl fmt::Debug for CustomPacketRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let handlers = self.handlers.read().expect("registry lock poisoned");
+        f.debug_struct("CustomPacketRegistry")
+            .field("handlers", &handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl CustomPacketRegistry {
+    pub fn new() -> Self {
+        CustomPacketRegistry::default()
+    }
+
+    /// Registers a handler for a vendor ID and subtype
+    ///
+    /// Returns the previously registered handler.
+    pub fn register(
+        &self,
+        vendor_id: u32,
+        subtype: u16,
+        handler: impl CustomPacketHandler + 'static,
+    ) -> Option<Arc<dyn CustomPacketHandler>> {
+        self.handlers
+            .write()
+            .expect("registry lock poisoned")
+            .insert((vendor_id, subtype), Arc::new(handler))
+    }
+
+    /// Removes the handler for a vendor ID and subtype
+    pub fn unregister(&self, vendor_id: u32, subtype: u16) -> Option<Arc<dyn CustomPacketHandler>> {
+        self.handlers
+            .write()
+            .expect("registry lock poisoned")
+            .remove(&(vendor_id, subtype))
+    }
+
+    /// Dispatches a custom packet to its handler
+    ///
+    /// Returns the frame which should be send back to the peer.
+    pub fn dispatch(&self, packet: CustomPacket) -> Option<Frame> {
+        let handler = self
+            .handlers
+            .read()
+            .expect("registry lock poisoned")
+            .get(&(packet.vendor_id, packet.subtype))
+            .cloned();
+        match handler {
+            Some(handler) => handler.handle(packet),
+            None => {
+                let err = ProtocolError::UnknownCustomPacket {
+                    vendor_id: packet.vendor_id,
+                    subtype: packet.subtype,
+                };
+                ErrorPacket::try_from(&err).ok().map(Frame::Error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::custom::{CustomPacket, CustomPacketRegistry};
+    use crate::protocol::error::ProtocolError;
+    use crate::protocol::frame::{Frame, PacketPayload, PacketType};
+    use bytes::{Bytes, BytesMut};
+
+    const VENDOR_ID: u32 = 0x1234_5678;
+
+    #[test]
+    fn test_encode_decode_custom_packet() {
+        let testvec = vec![
+            CustomPacket::new(VENDOR_ID, 1, Bytes::new()),
+            CustomPacket::new(u32::MAX, u16::MAX, Bytes::from_static(b"telemetry")),
+        ];
+
+        for case in testvec {
+            let mut encoded = BytesMut::new();
+            case.encode_payload(&mut encoded).unwrap();
+            let decoded = CustomPacket::decode_payload(encoded.freeze()).unwrap();
+            assert_eq!(case, decoded);
+        }
+
+        assert!(matches!(
+            CustomPacket::decode_payload(Bytes::from_static(&[0, 0, 0, 1, 0])),
+            Err(ProtocolError::MalformedPacket(PacketType::Custom))
+        ));
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let registry = CustomPacketRegistry::new();
+        registry.register(VENDOR_ID, 1, |packet: CustomPacket| {
+            Some(Frame::Custom(CustomPacket::new(
+                packet.vendor_id,
+                2,
+                packet.data,
+            )))
+        });
+
+        assert_eq!(
+            registry.dispatch(CustomPacket::new(VENDOR_ID, 1, vec![42])),
+            Some(Frame::Custom(CustomPacket::new(VENDOR_ID, 2, vec![42])))
+        );
+
+        let response = registry.dispatch(CustomPacket::new(VENDOR_ID, 2, vec![42]));
+        match response {
+            Some(Frame::Error(packet)) => assert!(matches!(
+                ProtocolError::from(packet),
+                ProtocolError::UnknownCustomPacket {
+                    vendor_id: VENDOR_ID,
+                    subtype: 2
+                }
+            )),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        assert!(registry.unregister(VENDOR_ID, 1).is_some());
+        assert!(matches!(
+            registry.dispatch(CustomPacket::new(VENDOR_ID, 1, vec![])),
+            Some(Frame::Error(_))
+        ));
+    }
+}
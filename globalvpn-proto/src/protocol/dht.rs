@@ -0,0 +1,228 @@
+//! FIND_NODE, FIND_VALUE and NODES packets
+//!
+//! Used by dictionary nodes holding a partial dictionary, see [`crate::dht`].
+//!
+//! FIND_NODE asks for the contacts closest to a target NodeId, which are returned
+//! in a NODES packet. FIND_VALUE asks for the certificate of the target NodeId.
+//! It is answered with a LOOKUP_RESPONSE packet if the certificate is stored,
+//! otherwise with the closest contacts in a NODES packet.
+//! Certificates are stored on a node by sending them in an UPDATE packet.
+//!
+//! Contacts are exchanged as their certificates, so they are self-authenticating
+//! and contain the reachability information of the node.
+//!
+//! ## FIND_NODE and FIND_VALUE
+//!
+//! | Type  | Name                                    |
+//! | ----- | --------------------------------------- |
+//! | u32   | Request ID                              |
+//! | 32    | Target NodeId                           |
+//! | bytes | DER encoded certificate of the sender   |
+//!
+//! The certificate of the sender is empty if the sender does not take part in the
+//! distributed dictionary and should not be added to the routing table.
+//!
+//! ## NODES
+//!
+//! | Type | Name       |
+//! | ---- | ---------- |
+//! | u32  | Request ID |
+//! | ~    | Contacts   |
+//!
+//! Each contact is encoded as:
+//!
+//! | Type  | Name                          |
+//! | ----- | ----------------------------- |
+//! | u16   | Length of the certificate     |
+//! | bytes | DER encoded X.509 certificate |
+
+use crate::certificate::RawCertificate;
+use crate::data::{NodeId, NODE_ID_BYTES};
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::{PacketPayload, PacketType, MAX_PAYLOAD_LENGTH};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::convert::TryFrom;
+
+/// Payload of the FIND_NODE and FIND_VALUE packets
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FindPacket {
+    pub request_id: u32,
+    pub target: NodeId,
+    /// Certificate of the sender, if it takes part in the distributed dictionary
+    pub sender: Option<RawCertificate>,
+}
+
+impl FindPacket {
+    /// Decodes a FIND_NODE or FIND_VALUE payload
+    pub(crate) fn decode(mut payload: Bytes, packet_type: PacketType) -> ProtocolResult<Self> {
+        let malformed = ProtocolError::MalformedPacket(packet_type);
+        if payload.remaining() < 4 + NODE_ID_BYTES {
+            return Err(malformed);
+        }
+        let request_id = payload.get_u32();
+        let target = NodeId::try_from(&payload.split_to(NODE_ID_BYTES)[..])
+            .map_err(|_err| malformed.clone())?;
+        let sender = if payload.is_empty() {
+            None
+        } else {
+            let certificate = RawCertificate::from_der(payload.to_vec());
+            certificate.node_id().map_err(|_err| malformed)?;
+            Some(certificate)
+        };
+        Ok(FindPacket {
+            request_id,
+            target,
+            sender,
+        })
+    }
+
+    /// Encodes a FIND_NODE or FIND_VALUE payload
+    ///
+    /// Not implemented as [`PacketPayload`], as decoding depends on the packet type.
+    pub(crate) fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u32(self.request_id);
+        dst.put_slice(self.target.as_bytes());
+        if let Some(sender) = &self.sender {
+            dst.put_slice(sender.der());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NodesPacket {
+    pub request_id: u32,
+    pub contacts: Vec<RawCertificate>,
+}
+
+impl NodesPacket {
+    /// Creates a packet with as many of the given contacts as fit into a single packet
+    pub fn new(request_id: u32, contacts: impl IntoIterator<Item = RawCertificate>) -> Self {
+        let mut length = 4;
+        let contacts = contacts
+            .into_iter()
+            .take_while(|certificate| {
+                length += 2 + certificate.der().len();
+                length <= MAX_PAYLOAD_LENGTH
+            })
+            .collect();
+        NodesPacket {
+            request_id,
+            contacts,
+        }
+    }
+}
+
+impl PacketPayload for NodesPacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u32(self.request_id);
+        for certificate in &self.contacts {
+            let length = u16::try_from(certificate.der().len())
+                .map_err(|_err| ProtocolError::MalformedPacket(PacketType::Nodes))?;
+            dst.put_u16(length);
+            dst.put_slice(certificate.der());
+        }
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        let malformed = ProtocolError::MalformedPacket(PacketType::Nodes);
+        if payload.remaining() < 4 {
+            return Err(malformed);
+        }
+        let request_id = payload.get_u32();
+        let mut contacts = Vec::new();
+        while payload.has_remaining() {
+            if payload.remaining() < 2 {
+                return Err(malformed);
+            }
+            let length = payload.get_u16() as usize;
+            if payload.remaining() < length {
+                return Err(malformed);
+            }
+            let certificate = RawCertificate::from_der(payload.split_to(length).to_vec());
+            certificate.node_id().map_err(|_err| malformed.clone())?;
+            contacts.push(certificate);
+        }
+        Ok(NodesPacket {
+            request_id,
+            contacts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::NodeId;
+    use crate::protocol::dht::{FindPacket, NodesPacket};
+    use crate::protocol::error::ProtocolError;
+    use crate::protocol::frame::{Frame, FrameCodec, PacketType, MAX_PAYLOAD_LENGTH};
+    use crate::test_util;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn test_encode_decode_dht_packets() {
+        let find = |sender| FindPacket {
+            request_id: 1,
+            target: NodeId::from([0x42; 32]),
+            sender,
+        };
+        let testvec = vec![
+            Frame::FindNode(find(None)),
+            Frame::FindNode(find(Some(test_util::certificate()))),
+            Frame::FindValue(find(Some(test_util::certificate()))),
+            Frame::Nodes(NodesPacket::new(
+                2,
+                vec![test_util::certificate(), test_util::certificate()],
+            )),
+            Frame::Nodes(NodesPacket::new(3, Vec::new())),
+        ];
+
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        for case in &testvec {
+            codec.encode(case, &mut buf).unwrap();
+        }
+        for case in testvec {
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(case));
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_nodes_packet_fits_into_frame() {
+        let certificate = test_util::certificate();
+        let count = MAX_PAYLOAD_LENGTH / certificate.der().len() + 10;
+        let packet = NodesPacket::new(1, vec![certificate.clone(); count]);
+        assert!(packet.contacts.len() < count);
+        assert!(packet.contacts.len() >= MAX_PAYLOAD_LENGTH / (certificate.der().len() + 2) - 1);
+
+        let mut buf = BytesMut::new();
+        FrameCodec::new()
+            .encode(Frame::Nodes(packet), &mut buf)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_decode_malformed_find_packets() {
+        for packet_type in &[PacketType::FindNode, PacketType::FindValue] {
+            // the request ID is not followed by a target NodeId
+            let mut buf = BytesMut::from(&[0x00, 0x04, *packet_type as u8, 0, 0, 0, 1][..]);
+            assert!(matches!(
+                FrameCodec::new().decode(&mut buf),
+                Err(ProtocolError::MalformedPacket(reported)) if reported == *packet_type
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_malformed_nodes_packet() {
+        let mut buf =
+            BytesMut::from(&[0x00, 0x07, 0x0c, 0x00, 0x00, 0x00, 0x01, 0x00, 0x10, 0x30][..]);
+        assert!(matches!(
+            FrameCodec::new().decode(&mut buf),
+            Err(ProtocolError::MalformedPacket(PacketType::Nodes))
+        ));
+    }
+}
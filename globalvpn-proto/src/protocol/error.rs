@@ -1,11 +1,344 @@
 //! Error which can be send to another node
+//!
+//! Errors are transmitted using the ERROR packet.
+//!
+//! | Type  | Name          |
+//! | ----- | ------------- |
+//! | u8    | Error code    |
+//! | u8    | Error subcode |
+//! | bytes | Data          |
+//!
+//! ## Error codes
+//!
+//! | Code | Subcode | Error                                 | Data                           |
+//! | ---- | ------- | ------------------------------------- | ------------------------------ |
+//! | 0x01 | 0x01    | [`ProtocolError::UnexpectedEof`]      |                                |
+//! | 0x01 | 0x02    | [`ProtocolError::PayloadTooLarge`]    | u32 length, u32 maximum length |
+//! | 0x01 | 0x03    | [`ProtocolError::UnknownPacketType`]  | u8 packet type                 |
+//! | 0x01 | 0x04    | [`ProtocolError::MalformedPacket`]    | u8 packet type                 |
+//! | 0x02 | 0x01    | [`ProtocolError::UnsupportedVersion`] | u8 version                     |
+//! | 0x03 | 0x01    | [`ProtocolError::KeepaliveTimeout`]   |                                |
+//! | 0x04 | 0x01    | [`ProtocolError::UnknownCustomPacket`] | u32 vendor ID, u16 subtype    |
+//! | 0x05 | 0x01    | [`ProtocolError::NodeNotFound`]       | u32 request ID, 32 NodeId      |
+//! | 0x06 | 0x01    | [`ProtocolError::DigestTooLarge`]     | u32 maximum number of entries  |
+//!
+//! Errors with an unknown code or subcode are decoded as [`ProtocolError::Unknown`].
 
+use crate::data::{NodeId, NODE_ID_BYTES};
+use crate::protocol::frame::{PacketPayload, PacketType};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::convert::TryFrom;
+use std::io;
+use std::sync::Arc;
 use thiserror::Error;
 
 pub type ProtocolResult<T> = Result<T, ProtocolError>;
 
+/// Error code for errors while decoding a frame
+pub const ERROR_CODE_FRAME: u8 = 0x01;
+/// Error code for errors in the OPEN packet
+pub const ERROR_CODE_OPEN: u8 = 0x02;
+/// Error code for errors of an established session
+pub const ERROR_CODE_SESSION: u8 = 0x03;
+/// Error code for errors in the CUSTOM packet
+pub const ERROR_CODE_CUSTOM: u8 = 0x04;
+/// Error code for errors answering a LOOKUP packet
+pub const ERROR_CODE_LOOKUP: u8 = 0x05;
+/// Error code for errors answering a SYNC_DIGEST packet
+pub const ERROR_CODE_SYNC: u8 = 0x06;
+
+/// Length of the data of [`ProtocolError::NodeNotFound`]
+const NODE_NOT_FOUND_LENGTH: usize = 4 + NODE_ID_BYTES;
+
 #[derive(Error, Debug, Clone)]
 pub enum ProtocolError {
     #[error("Unexpected EOF")]
     UnexpectedEof,
+    #[error("Payload of {length} bytes exceeds the maximum of {max_length} bytes")]
+    PayloadTooLarge { length: usize, max_length: usize },
+    #[error("Unknown packet type {0:#04x}")]
+    UnknownPacketType(u8),
+    #[error("No common protocol version, remote prefers version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Malformed {0:?} packet")]
+    MalformedPacket(PacketType),
+    #[error("No packet received from peer in time")]
+    KeepaliveTimeout,
+    #[error(
+        "No handler for custom packet with vendor ID {vendor_id:#010x} and subtype {subtype:#06x}"
+    )]
+    UnknownCustomPacket { vendor_id: u32, subtype: u16 },
+    #[error("Node {node_id} of lookup {request_id} not found")]
+    NodeNotFound { request_id: u32, node_id: NodeId },
+    #[error("Sync digest exceeds the maximum of {max_entries} entries")]
+    DigestTooLarge { max_entries: u32 },
+    #[error("Unknown error with code {code:#04x} and subcode {subcode:#04x}")]
+    Unknown { code: u8, subcode: u8, data: Bytes },
+    #[error("Connection is closed")]
+    ConnectionClosed,
+    #[error("Malformed encrypted record")]
+    MalformedRecord,
+    #[error("Decryption of record failed")]
+    DecryptionFailed,
+    #[error("Replayed record, expected counter {expected}, received {received}")]
+    ReplayedRecord { expected: u64, received: u64 },
+    #[error("I/O error: {0}")]
+    Io(Arc<io::Error>),
+}
+
+impl From<io::Error> for ProtocolError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            ProtocolError::UnexpectedEof
+        } else {
+            ProtocolError::Io(Arc::new(err))
+        }
+    }
+}
+
+/// Payload of the ERROR packet
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ErrorPacket {
+    pub code: u8,
+    pub subcode: u8,
+    pub data: Bytes,
+}
+
+impl ErrorPacket {
+    pub fn new(code: u8, subcode: u8, data: impl Into<Bytes>) -> Self {
+        ErrorPacket {
+            code,
+            subcode,
+            data: data.into(),
+        }
+    }
+}
+
+impl PacketPayload for ErrorPacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u8(self.code);
+        dst.put_u8(self.subcode);
+        dst.put_slice(&self.data);
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        if payload.remaining() < 2 {
+            return Err(ProtocolError::MalformedPacket(PacketType::Error));
+        }
+        let code = payload.get_u8();
+        let subcode = payload.get_u8();
+        Ok(ErrorPacket {
+            code,
+            subcode,
+            data: payload,
+        })
+    }
+}
+
+/// Errors which only occur locally can not be send to another node
+impl TryFrom<&ProtocolError> for ErrorPacket {
+    type Error = ();
+
+    fn try_from(value: &ProtocolError) -> Result<Self, Self::Error> {
+        Ok(match value {
+            ProtocolError::UnexpectedEof => ErrorPacket::new(ERROR_CODE_FRAME, 0x01, Bytes::new()),
+            ProtocolError::PayloadTooLarge { length, max_length } => {
+                let mut data = BytesMut::with_capacity(8);
+                data.put_u32(u32::try_from(*length).unwrap_or(u32::MAX));
+                data.put_u32(u32::try_from(*max_length).unwrap_or(u32::MAX));
+                ErrorPacket::new(ERROR_CODE_FRAME, 0x02, data)
+            }
+            ProtocolError::UnknownPacketType(packet_type) => {
+                ErrorPacket::new(ERROR_CODE_FRAME, 0x03, vec![*packet_type])
+            }
+            ProtocolError::MalformedPacket(packet_type) => {
+                ErrorPacket::new(ERROR_CODE_FRAME, 0x04, vec![*packet_type as u8])
+            }
+            ProtocolError::UnsupportedVersion(version) => {
+                ErrorPacket::new(ERROR_CODE_OPEN, 0x01, vec![*version])
+            }
+            ProtocolError::KeepaliveTimeout => {
+                ErrorPacket::new(ERROR_CODE_SESSION, 0x01, Bytes::new())
+            }
+            ProtocolError::UnknownCustomPacket { vendor_id, subtype } => {
+                let mut data = BytesMut::with_capacity(6);
+                data.put_u32(*vendor_id);
+                data.put_u16(*subtype);
+                ErrorPacket::new(ERROR_CODE_CUSTOM, 0x01, data)
+            }
+            ProtocolError::NodeNotFound {
+                request_id,
+                node_id,
+            } => {
+                let mut data = BytesMut::with_capacity(NODE_NOT_FOUND_LENGTH);
+                data.put_u32(*request_id);
+                data.put_slice(node_id.as_bytes());
+                ErrorPacket::new(ERROR_CODE_LOOKUP, 0x01, data)
+            }
+            ProtocolError::DigestTooLarge { max_entries } => {
+                ErrorPacket::new(ERROR_CODE_SYNC, 0x01, max_entries.to_be_bytes().to_vec())
+            }
+            ProtocolError::Unknown {
+                code,
+                subcode,
+                data,
+            } => ErrorPacket::new(*code, *subcode, data.clone()),
+            ProtocolError::ConnectionClosed
+            | ProtocolError::MalformedRecord
+            | ProtocolError::DecryptionFailed
+            | ProtocolError::ReplayedRecord { .. }
+            | ProtocolError::Io(_) => return Err(()),
+        })
+    }
+}
+
+impl TryFrom<ProtocolError> for ErrorPacket {
+    type Error = ProtocolError;
+
+    fn try_from(value: ProtocolError) -> Result<Self, Self::Error> {
+        ErrorPacket::try_from(&value).map_err(|()| value)
+    }
+}
+
+impl From<ErrorPacket> for ProtocolError {
+    fn from(packet: ErrorPacket) -> Self {
+        let data = packet.data.as_ref();
+        let known = match (packet.code, packet.subcode, data.len()) {
+            (ERROR_CODE_FRAME, 0x01, 0) => Some(ProtocolError::UnexpectedEof),
+            (ERROR_CODE_FRAME, 0x02, 8) => {
+                let mut data = data;
+                Some(ProtocolError::PayloadTooLarge {
+                    length: data.get_u32() as usize,
+                    max_length: data.get_u32() as usize,
+                })
+            }
+            (ERROR_CODE_FRAME, 0x03, 1) => Some(ProtocolError::UnknownPacketType(data[0])),
+            (ERROR_CODE_FRAME, 0x04, 1) => PacketType::try_from(data[0])
+                .ok()
+                .map(ProtocolError::MalformedPacket),
+            (ERROR_CODE_OPEN, 0x01, 1) => Some(ProtocolError::UnsupportedVersion(data[0])),
+            (ERROR_CODE_SESSION, 0x01, 0) => Some(ProtocolError::KeepaliveTimeout),
+            (ERROR_CODE_CUSTOM, 0x01, 6) => {
+                let mut data = data;
+                Some(ProtocolError::UnknownCustomPacket {
+                    vendor_id: data.get_u32(),
+                    subtype: data.get_u16(),
+                })
+            }
+            (ERROR_CODE_LOOKUP, 0x01, NODE_NOT_FOUND_LENGTH) => {
+                let mut data = data;
+                let request_id = data.get_u32();
+                NodeId::try_from(data)
+                    .ok()
+                    .map(|node_id| ProtocolError::NodeNotFound {
+                        request_id,
+                        node_id,
+                    })
+            }
+            (ERROR_CODE_SYNC, 0x01, 4) => {
+                let mut data = data;
+                Some(ProtocolError::DigestTooLarge {
+                    max_entries: data.get_u32(),
+                })
+            }
+            _ => None,
+        };
+        known.unwrap_or(ProtocolError::Unknown {
+            code: packet.code,
+            subcode: packet.subcode,
+            data: packet.data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::NodeId;
+    use crate::protocol::error::{ErrorPacket, ProtocolError};
+    use crate::protocol::frame::{PacketPayload, PacketType};
+    use bytes::{Bytes, BytesMut};
+    use std::convert::TryFrom;
+    use std::io;
+
+    #[test]
+    fn test_encode_decode_error_packet() {
+        let testvec = vec![
+            ErrorPacket::new(1, 2, Bytes::new()),
+            ErrorPacket::new(0xff, 0xfe, Bytes::from_static(b"data")),
+        ];
+
+        for case in testvec {
+            let mut encoded = BytesMut::new();
+            case.encode_payload(&mut encoded).unwrap();
+            let decoded = ErrorPacket::decode_payload(encoded.freeze()).unwrap();
+            assert_eq!(case, decoded);
+        }
+
+        assert!(matches!(
+            ErrorPacket::decode_payload(Bytes::from_static(&[1])),
+            Err(ProtocolError::MalformedPacket(PacketType::Error))
+        ));
+    }
+
+    #[test]
+    fn test_protocol_error_roundtrip() {
+        let testvec = vec![
+            ProtocolError::UnexpectedEof,
+            ProtocolError::PayloadTooLarge {
+                length: 70000,
+                max_length: 65535,
+            },
+            ProtocolError::UnknownPacketType(0x42),
+            ProtocolError::MalformedPacket(PacketType::Open),
+            ProtocolError::UnsupportedVersion(3),
+            ProtocolError::KeepaliveTimeout,
+            ProtocolError::UnknownCustomPacket {
+                vendor_id: 0x1234_5678,
+                subtype: 0x0102,
+            },
+            ProtocolError::NodeNotFound {
+                request_id: 7,
+                node_id: NodeId::from([0x42; 32]),
+            },
+            ProtocolError::DigestTooLarge {
+                max_entries: 1 << 20,
+            },
+            ProtocolError::Unknown {
+                code: 0x80,
+                subcode: 0x01,
+                data: Bytes::from_static(b"vendor"),
+            },
+        ];
+
+        for case in testvec {
+            let packet = ErrorPacket::try_from(&case).unwrap();
+            let decoded = ProtocolError::from(packet);
+            // ProtocolError can not implement PartialEq because of io::Error
+            assert_eq!(format!("{:?}", case), format!("{:?}", decoded));
+        }
+    }
+
+    #[test]
+    fn test_unknown_error_code() {
+        let packet = ErrorPacket::new(0x01, 0x04, vec![0x42]);
+        assert!(matches!(
+            ProtocolError::from(packet.clone()),
+            ProtocolError::Unknown {
+                code: 0x01,
+                subcode: 0x04,
+                ..
+            }
+        ));
+        assert_eq!(
+            ErrorPacket::try_from(&ProtocolError::from(packet.clone())).unwrap(),
+            packet
+        );
+    }
+
+    #[test]
+    fn test_local_error_is_not_sent() {
+        let err = ProtocolError::from(io::Error::new(io::ErrorKind::BrokenPipe, "local"));
+        assert!(ErrorPacket::try_from(err).is_err());
+    }
 }
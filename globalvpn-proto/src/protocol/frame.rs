@@ -0,0 +1,359 @@
+//! Framing of packets
+//!
+//! Every packet is prefixed with its payload length and packet type.
+//! All integers are encoded in network byte order.
+//!
+//! | Type  | Name        | Comment                  |
+//! | ----- | ----------- | ------------------------ |
+//! | u16   | Length      | describes payload length |
+//! | u8    | Packet Type |                          |
+//! | bytes | Payload     |                          |
+//!
+//! The length field limits the payload to [`MAX_PAYLOAD_LENGTH`] (65535) bytes.
+
+use crate::protocol::custom::CustomPacket;
+use crate::protocol::dht::{FindPacket, NodesPacket};
+use crate::protocol::error::{ErrorPacket, ProtocolError, ProtocolResult};
+use crate::protocol::lookup::{LookupPacket, LookupResponsePacket};
+use crate::protocol::open::OpenPacket;
+use crate::protocol::sync::{SyncDigestPacket, SyncDonePacket};
+use crate::protocol::update::UpdatePacket;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::convert::TryFrom;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Length of the frame header (length and packet type) in bytes
+pub const FRAME_HEADER_LENGTH: usize = 3;
+
+/// Maximum length of a payload which can be described by the length field
+pub const MAX_PAYLOAD_LENGTH: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[repr(u8)]
+pub enum PacketType {
+    Open = 0x01,
+    Update = 0x02,
+    Error = 0x03,
+    Keepalive = 0x04,
+    Custom = 0x05,
+    SyncDigest = 0x06,
+    SyncDone = 0x07,
+    Lookup = 0x08,
+    LookupResponse = 0x09,
+    FindNode = 0x0a,
+    FindValue = 0x0b,
+    Nodes = 0x0c,
+}
+
+impl TryFrom<u8> for PacketType {
+    type Error = ProtocolError;
+
+    fn try_from(value: u8) -> ProtocolResult<Self> {
+        match value {
+            0x01 => Ok(PacketType::Open),
+            0x02 => Ok(PacketType::Update),
+            0x03 => Ok(PacketType::Error),
+            0x04 => Ok(PacketType::Keepalive),
+            0x05 => Ok(PacketType::Custom),
+            0x06 => Ok(PacketType::SyncDigest),
+            0x07 => Ok(PacketType::SyncDone),
+            0x08 => Ok(PacketType::Lookup),
+            0x09 => Ok(PacketType::LookupResponse),
+            0x0a => Ok(PacketType::FindNode),
+            0x0b => Ok(PacketType::FindValue),
+            0x0c => Ok(PacketType::Nodes),
+            other => Err(ProtocolError::UnknownPacketType(other)),
+        }
+    }
+}
+
+/// Encoding of the payload of a packet type
+pub trait PacketPayload: Sized {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()>;
+    fn decode_payload(payload: Bytes) -> ProtocolResult<Self>;
+}
+
+/// A single packet
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Frame {
+    Open(OpenPacket),
+    Update(UpdatePacket),
+    Error(ErrorPacket),
+    Keepalive,
+    Custom(CustomPacket),
+    SyncDigest(SyncDigestPacket),
+    SyncDone(SyncDonePacket),
+    Lookup(LookupPacket),
+    LookupResponse(LookupResponsePacket),
+    FindNode(FindPacket),
+    FindValue(FindPacket),
+    Nodes(NodesPacket),
+}
+
+impl Frame {
+    pub fn packet_type(&self) -> PacketType {
+        match self {
+            Frame::Open(_) => PacketType::Open,
+            Frame::Update(_) => PacketType::Update,
+            Frame::Error(_) => PacketType::Error,
+            Frame::Keepalive => PacketType::Keepalive,
+            Frame::Custom(_) => PacketType::Custom,
+            Frame::SyncDigest(_) => PacketType::SyncDigest,
+            Frame::SyncDone(_) => PacketType::SyncDone,
+            Frame::Lookup(_) => PacketType::Lookup,
+            Frame::LookupResponse(_) => PacketType::LookupResponse,
+            Frame::FindNode(_) => PacketType::FindNode,
+            Frame::FindValue(_) => PacketType::FindValue,
+            Frame::Nodes(_) => PacketType::Nodes,
+        }
+    }
+
+    fn from_parts(packet_type: PacketType, payload: Bytes) -> ProtocolResult<Self> {
+        match packet_type {
+            PacketType::Open => Ok(Frame::Open(OpenPacket::decode_payload(payload)?)),
+            PacketType::Update => Ok(Frame::Update(UpdatePacket::decode_payload(payload)?)),
+            PacketType::Error => Ok(Frame::Error(ErrorPacket::decode_payload(payload)?)),
+            PacketType::Keepalive if payload.is_empty() => Ok(Frame::Keepalive),
+            PacketType::Keepalive => Err(ProtocolError::MalformedPacket(packet_type)),
+            PacketType::Custom => Ok(Frame::Custom(CustomPacket::decode_payload(payload)?)),
+            PacketType::SyncDigest => Ok(Frame::SyncDigest(SyncDigestPacket::decode_payload(
+                payload,
+            )?)),
+            PacketType::SyncDone => Ok(Frame::SyncDone(SyncDonePacket::decode_payload(payload)?)),
+            PacketType::Lookup => Ok(Frame::Lookup(LookupPacket::decode_payload(payload)?)),
+            PacketType::LookupResponse => Ok(Frame::LookupResponse(
+                LookupResponsePacket::decode_payload(payload)?,
+            )),
+            PacketType::FindNode => Ok(Frame::FindNode(FindPacket::decode(
+                payload,
+                PacketType::FindNode,
+            )?)),
+            PacketType::FindValue => Ok(Frame::FindValue(FindPacket::decode(
+                payload,
+                PacketType::FindValue,
+            )?)),
+            PacketType::Nodes => Ok(Frame::Nodes(NodesPacket::decode_payload(payload)?)),
+        }
+    }
+
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        match self {
+            Frame::Open(packet) => packet.encode_payload(dst)?,
+            Frame::Error(packet) => packet.encode_payload(dst)?,
+            Frame::Update(packet) => packet.encode_payload(dst)?,
+            Frame::Custom(packet) => packet.encode_payload(dst)?,
+            Frame::SyncDigest(packet) => packet.encode_payload(dst)?,
+            Frame::SyncDone(packet) => packet.encode_payload(dst)?,
+            Frame::Lookup(packet) => packet.encode_payload(dst)?,
+            Frame::LookupResponse(packet) => packet.encode_payload(dst)?,
+            Frame::FindNode(packet) | Frame::FindValue(packet) => packet.encode_payload(dst)?,
+            Frame::Nodes(packet) => packet.encode_payload(dst)?,
+            Frame::Keepalive => {}
+        }
+        Ok(())
+    }
+}
+
+/// Encoder and decoder for [`Frame`]s
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FrameCodec {
+    max_payload_length: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        FrameCodec {
+            max_payload_length: MAX_PAYLOAD_LENGTH,
+        }
+    }
+}
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        FrameCodec::default()
+    }
+
+    /// Limits the accepted payload length below [`MAX_PAYLOAD_LENGTH`]
+    pub fn with_max_payload_length(max_payload_length: usize) -> Self {
+        FrameCodec {
+            max_payload_length: max_payload_length.min(MAX_PAYLOAD_LENGTH),
+        }
+    }
+
+    pub fn max_payload_length(&self) -> usize {
+        self.max_payload_length
+    }
+
+    fn check_length(&self, length: usize) -> ProtocolResult<()> {
+        if length > self.max_payload_length {
+            Err(ProtocolError::PayloadTooLarge {
+                length,
+                max_length: self.max_payload_length,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> ProtocolResult<Option<Frame>> {
+        if src.len() < FRAME_HEADER_LENGTH {
+            return Ok(None);
+        }
+        let length = u16::from_be_bytes([src[0], src[1]]) as usize;
+        self.check_length(length)?;
+
+        let frame_length = FRAME_HEADER_LENGTH + length;
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(2);
+        let packet_type = PacketType::try_from(src.get_u8());
+        let payload = src.split_to(length).freeze();
+        Frame::from_parts(packet_type?, payload).map(Some)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> ProtocolResult<Option<Frame>> {
+        match self.decode(buf)? {
+            Some(frame) => Ok(Some(frame)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(ProtocolError::UnexpectedEof),
+        }
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> ProtocolResult<()> {
+        self.encode(&item, dst)
+    }
+}
+
+impl Encoder<&Frame> for FrameCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: &Frame, dst: &mut BytesMut) -> ProtocolResult<()> {
+        let mut payload = BytesMut::new();
+        item.encode_payload(&mut payload)?;
+        self.check_length(payload.len())?;
+
+        dst.reserve(FRAME_HEADER_LENGTH + payload.len());
+        dst.put_u16(payload.len() as u16);
+        dst.put_u8(item.packet_type() as u8);
+        dst.put_slice(&payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::custom::CustomPacket;
+    use crate::protocol::error::{ErrorPacket, ProtocolError};
+    use crate::protocol::frame::{Frame, FrameCodec, PacketType, MAX_PAYLOAD_LENGTH};
+    use crate::protocol::open::OpenPacket;
+    use bytes::{Bytes, BytesMut};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn test_encode_decode_frame() {
+        let testvec = vec![
+            Frame::Open(OpenPacket::new(&[1, 2])),
+            Frame::Error(ErrorPacket::new(1, 2, vec![3])),
+            Frame::Keepalive,
+            Frame::Custom(CustomPacket::new(1, 2, Bytes::new())),
+            Frame::Custom(CustomPacket::new(1, 2, vec![0x42; MAX_PAYLOAD_LENGTH - 6])),
+        ];
+
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        for case in &testvec {
+            codec.encode(case, &mut buf).unwrap();
+        }
+        for case in testvec {
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(case));
+        }
+        assert!(buf.is_empty());
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_encode_wire_format() {
+        let mut buf = BytesMut::new();
+        FrameCodec::new()
+            .encode(Frame::Error(ErrorPacket::new(0xaa, 0xbb, vec![])), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], &[0x00, 0x02, 0x03, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn test_decode_partial() {
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::from(&[0x00, 0x03, 0x03, 0x01][..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(matches!(
+            codec.decode_eof(&mut buf),
+            Err(ProtocolError::UnexpectedEof)
+        ));
+
+        buf.extend_from_slice(&[0x02, 0x03]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Frame::Error(ErrorPacket::new(1, 2, vec![3])))
+        );
+    }
+
+    #[test]
+    fn test_oversized_payload() {
+        let mut codec = FrameCodec::with_max_payload_length(4);
+        let mut buf = BytesMut::new();
+        assert!(matches!(
+            codec.encode(
+                Frame::Custom(CustomPacket::new(1, 2, Bytes::new())),
+                &mut buf
+            ),
+            Err(ProtocolError::PayloadTooLarge {
+                length: 6,
+                max_length: 4
+            })
+        ));
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&[0x00, 0x05, 0x05][..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(ProtocolError::PayloadTooLarge { .. })
+        ));
+
+        let mut buf = BytesMut::new();
+        assert!(matches!(
+            FrameCodec::new().encode(
+                Frame::Custom(CustomPacket::new(1, 2, vec![0; MAX_PAYLOAD_LENGTH])),
+                &mut buf
+            ),
+            Err(ProtocolError::PayloadTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_invalid_packet_type() {
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::from(&[0x00, 0x00, 0x42][..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(ProtocolError::UnknownPacketType(0x42))
+        ));
+
+        let mut buf = BytesMut::from(&[0x00, 0x01, 0x04, 0x00][..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(ProtocolError::MalformedPacket(PacketType::Keepalive))
+        ));
+    }
+}
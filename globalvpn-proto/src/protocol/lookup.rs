@@ -0,0 +1,158 @@
+//! LOOKUP and LOOKUP_RESPONSE packets
+//!
+//! A node asks a directory node for the certificate of another node.
+//! The request ID is chosen randomly by the requesting node and copied into the answer,
+//! so multiple lookups can be outstanding on the same connection.
+//!
+//! A directory node which does not know the node may forward the lookup to other
+//! directory nodes, keeping the request ID, see [`crate::directory::DictionaryProxy`].
+//! The hop limit is the number of times the lookup may still be forwarded
+//! and is decremented by every forwarding node.
+//!
+//! If the directory node does not know the node, it answers with an ERROR packet,
+//! see [`ProtocolError::NodeNotFound`].
+//!
+//! ## LOOKUP
+//!
+//! | Type | Name       |
+//! | ---- | ---------- |
+//! | u32  | Request ID |
+//! | u8   | Hop limit  |
+//! | 32   | NodeId     |
+//!
+//! ## LOOKUP_RESPONSE
+//!
+//! | Type  | Name                          |
+//! | ----- | ----------------------------- |
+//! | u32   | Request ID                    |
+//! | bytes | DER encoded X.509 certificate |
+//!
+//! As in the UPDATE packet, only the encoding of the certificate is checked while decoding.
+
+use crate::certificate::RawCertificate;
+use crate::data::{NodeId, NODE_ID_BYTES};
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::{PacketPayload, PacketType};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::convert::TryFrom;
+
+/// Hop limit of a lookup sent by the node asking for a certificate
+pub const DEFAULT_LOOKUP_HOP_LIMIT: u8 = 8;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LookupPacket {
+    pub request_id: u32,
+    pub hop_limit: u8,
+    pub node_id: NodeId,
+}
+
+impl PacketPayload for LookupPacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u32(self.request_id);
+        dst.put_u8(self.hop_limit);
+        dst.put_slice(self.node_id.as_bytes());
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        if payload.remaining() != 4 + 1 + NODE_ID_BYTES {
+            return Err(ProtocolError::MalformedPacket(PacketType::Lookup));
+        }
+        let request_id = payload.get_u32();
+        let hop_limit = payload.get_u8();
+        let node_id = NodeId::try_from(&payload[..])
+            .map_err(|_err| ProtocolError::MalformedPacket(PacketType::Lookup))?;
+        Ok(LookupPacket {
+            request_id,
+            hop_limit,
+            node_id,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LookupResponsePacket {
+    pub request_id: u32,
+    pub certificate: RawCertificate,
+}
+
+impl PacketPayload for LookupResponsePacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u32(self.request_id);
+        dst.put_slice(self.certificate.der());
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        let malformed = ProtocolError::MalformedPacket(PacketType::LookupResponse);
+        if payload.remaining() < 4 {
+            return Err(malformed);
+        }
+        let request_id = payload.get_u32();
+        let certificate = RawCertificate::from_der(payload.to_vec());
+        certificate.node_id().map_err(|_err| malformed)?;
+        Ok(LookupResponsePacket {
+            request_id,
+            certificate,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::NodeId;
+    use crate::protocol::error::{ErrorPacket, ProtocolError};
+    use crate::protocol::frame::{Frame, FrameCodec, PacketType};
+    use crate::protocol::lookup::{LookupPacket, LookupResponsePacket};
+    use crate::test_util;
+    use bytes::BytesMut;
+    use std::convert::TryFrom;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn test_encode_decode_lookup_packets() {
+        let certificate = test_util::certificate();
+        let not_found = ProtocolError::NodeNotFound {
+            request_id: 3,
+            node_id: NodeId::from([0x42; 32]),
+        };
+
+        let testvec = vec![
+            Frame::Lookup(LookupPacket {
+                request_id: 1,
+                hop_limit: 3,
+                node_id: NodeId::from([0x42; 32]),
+            }),
+            Frame::LookupResponse(LookupResponsePacket {
+                request_id: 2,
+                certificate,
+            }),
+            Frame::Error(ErrorPacket::try_from(&not_found).unwrap()),
+        ];
+
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        for case in &testvec {
+            codec.encode(case, &mut buf).unwrap();
+        }
+        for case in testvec {
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(case));
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_malformed_lookup_packets() {
+        let mut buf = BytesMut::from(&[0x00, 0x04, 0x08, 0x00, 0x00, 0x00, 0x01][..]);
+        assert!(matches!(
+            FrameCodec::new().decode(&mut buf),
+            Err(ProtocolError::MalformedPacket(PacketType::Lookup))
+        ));
+
+        let mut buf = BytesMut::from(&[0x00, 0x06, 0x09, 0x00, 0x00, 0x00, 0x01, 0x30, 0x00][..]);
+        assert!(matches!(
+            FrameCodec::new().decode(&mut buf),
+            Err(ProtocolError::MalformedPacket(PacketType::LookupResponse))
+        ));
+    }
+}
@@ -1,5 +1,13 @@
 //! Definition of types that will be send over the network
 
+pub mod connection;
+pub mod custom;
+pub mod dht;
 pub mod error;
+pub mod frame;
+pub mod lookup;
+pub mod open;
+pub mod sync;
+pub mod update;
 
-pub use crate::data::nodeid::NodeId;
+pub use crate::data::NodeId;
@@ -0,0 +1,250 @@
+//! OPEN packet
+//!
+//! The OPEN packet is the first packet send by both sides of a connection.
+//!
+//! | Type | Name                      |
+//! | ---- | ------------------------- |
+//! | u8   | Version                   |
+//! | u8   | Optional Parameters Count |
+//! | ~    | Optional Parameters       |
+//!
+//! The version field contains the highest supported protocol version.
+//! Further supported versions can be announced using the
+//! [`OptionalParameter::SupportedVersions`] parameter.
+//!
+//! ## Optional Parameters
+//!
+//! | Type    | Name             |
+//! | ------- | ---------------- |
+//! | u8      | Parameter type   |
+//! | u16     | Parameter length |
+//! | bytes   | Parameter value  |
+//!
+//! | Parameter type | Name              | Value                            |
+//! | -------------- | ----------------- | -------------------------------- |
+//! | 0x01           | SupportedVersions | `n` x u8 supported versions      |
+//!
+//! Unknown parameters are ignored, but preserved when decoding.
+
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::{PacketPayload, PacketType};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::BTreeSet;
+
+/// Protocol version implemented by this node
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// All protocol versions this node can speak
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u8] = &[PROTOCOL_VERSION];
+
+const PARAMETER_SUPPORTED_VERSIONS: u8 = 0x01;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OpenPacket {
+    pub version: u8,
+    pub optional_parameters: Vec<OptionalParameter>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum OptionalParameter {
+    /// Additional protocol versions supported by the sender
+    SupportedVersions(BTreeSet<u8>),
+    /// Parameter which is not known by this implementation
+    Unknown { parameter_type: u8, value: Bytes },
+}
+
+impl OptionalParameter {
+    pub fn parameter_type(&self) -> u8 {
+        match self {
+            OptionalParameter::SupportedVersions(_) => PARAMETER_SUPPORTED_VERSIONS,
+            OptionalParameter::Unknown { parameter_type, .. } => *parameter_type,
+        }
+    }
+
+    fn encode_value(&self, dst: &mut BytesMut) {
+        match self {
+            OptionalParameter::SupportedVersions(versions) => {
+                dst.extend(versions.iter());
+            }
+            OptionalParameter::Unknown { value, .. } => dst.put_slice(value),
+        }
+    }
+
+    fn decode_value(parameter_type: u8, value: Bytes) -> Self {
+        match parameter_type {
+            PARAMETER_SUPPORTED_VERSIONS => {
+                OptionalParameter::SupportedVersions(value.iter().copied().collect())
+            }
+            parameter_type => OptionalParameter::Unknown {
+                parameter_type,
+                value,
+            },
+        }
+    }
+}
+
+impl OpenPacket {
+    /// Creates an OPEN packet announcing the given protocol versions
+    ///
+    /// # Panics
+    ///
+    /// Panics if `supported_versions` is empty.
+    pub fn new(supported_versions: &[u8]) -> Self {
+        let versions: BTreeSet<u8> = supported_versions.iter().copied().collect();
+        let version = *versions
+            .iter()
+            .next_back()
+            .expect("at least one supported version");
+        let mut optional_parameters = Vec::new();
+        if versions.len() > 1 {
+            optional_parameters.push(OptionalParameter::SupportedVersions(versions));
+        }
+        OpenPacket {
+            version,
+            optional_parameters,
+        }
+    }
+
+    /// All protocol versions supported by the sender of this packet
+    pub fn supported_versions(&self) -> BTreeSet<u8> {
+        let mut versions = BTreeSet::new();
+        versions.insert(self.version);
+        for parameter in &self.optional_parameters {
+            if let OptionalParameter::SupportedVersions(additional) = parameter {
+                versions.extend(additional.iter().copied());
+            }
+        }
+        versions
+    }
+
+    /// Picks the highest protocol version supported by both sides
+    pub fn negotiate_version(&self, local_versions: &[u8]) -> ProtocolResult<u8> {
+        let remote_versions = self.supported_versions();
+        local_versions
+            .iter()
+            .copied()
+            .filter(|version| remote_versions.contains(version))
+            .max()
+            .ok_or(ProtocolError::UnsupportedVersion(self.version))
+    }
+}
+
+impl Default for OpenPacket {
+    fn default() -> Self {
+        OpenPacket::new(SUPPORTED_PROTOCOL_VERSIONS)
+    }
+}
+
+impl PacketPayload for OpenPacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        if self.optional_parameters.len() > u8::MAX as usize {
+            return Err(ProtocolError::MalformedPacket(PacketType::Open));
+        }
+        dst.put_u8(self.version);
+        dst.put_u8(self.optional_parameters.len() as u8);
+        for parameter in &self.optional_parameters {
+            let mut value = BytesMut::new();
+            parameter.encode_value(&mut value);
+            if value.len() > u16::MAX as usize {
+                return Err(ProtocolError::MalformedPacket(PacketType::Open));
+            }
+            dst.put_u8(parameter.parameter_type());
+            dst.put_u16(value.len() as u16);
+            dst.put_slice(&value);
+        }
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        let malformed = || ProtocolError::MalformedPacket(PacketType::Open);
+        if payload.remaining() < 2 {
+            return Err(malformed());
+        }
+        let version = payload.get_u8();
+        let count = payload.get_u8();
+
+        let mut optional_parameters = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if payload.remaining() < 3 {
+                return Err(malformed());
+            }
+            let parameter_type = payload.get_u8();
+            let length = payload.get_u16() as usize;
+            if payload.remaining() < length {
+                return Err(malformed());
+            }
+            let value = payload.split_to(length);
+            optional_parameters.push(OptionalParameter::decode_value(parameter_type, value));
+        }
+        if payload.has_remaining() {
+            return Err(malformed());
+        }
+
+        Ok(OpenPacket {
+            version,
+            optional_parameters,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::error::ProtocolError;
+    use crate::protocol::frame::{PacketPayload, PacketType};
+    use crate::protocol::open::{OpenPacket, OptionalParameter};
+    use bytes::{Bytes, BytesMut};
+
+    #[test]
+    fn test_encode_decode_open_packet() {
+        let testvec = vec![
+            OpenPacket::new(&[1]),
+            OpenPacket::new(&[1, 2, 5]),
+            OpenPacket {
+                version: 3,
+                optional_parameters: vec![
+                    OptionalParameter::Unknown {
+                        parameter_type: 0xfe,
+                        value: Bytes::from_static(b"unknown"),
+                    },
+                    OptionalParameter::SupportedVersions(vec![1, 2].into_iter().collect()),
+                ],
+            },
+        ];
+
+        for case in testvec {
+            let mut encoded = BytesMut::new();
+            case.encode_payload(&mut encoded).unwrap();
+            let decoded = OpenPacket::decode_payload(encoded.freeze()).unwrap();
+            assert_eq!(case, decoded);
+        }
+    }
+
+    #[test]
+    fn test_decode_malformed_open_packet() {
+        let testvec: Vec<&[u8]> = vec![
+            &[],
+            &[1],
+            &[1, 1],
+            &[1, 1, 0x01, 0x00, 0x02, 0x01],
+            &[1, 0, 0xff],
+        ];
+        for case in testvec {
+            assert!(matches!(
+                OpenPacket::decode_payload(Bytes::copy_from_slice(case)),
+                Err(ProtocolError::MalformedPacket(PacketType::Open))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        let remote = OpenPacket::new(&[1, 2, 3]);
+        assert_eq!(remote.negotiate_version(&[1, 2]).unwrap(), 2);
+        assert_eq!(remote.negotiate_version(&[3, 4]).unwrap(), 3);
+        assert_eq!(OpenPacket::new(&[1]).negotiate_version(&[1]).unwrap(), 1);
+        assert!(matches!(
+            remote.negotiate_version(&[4, 5]),
+            Err(ProtocolError::UnsupportedVersion(3))
+        ));
+    }
+}
@@ -0,0 +1,224 @@
+//! SYNC_DIGEST and SYNC_DONE packets
+//!
+//! Used to synchronize the warm table with a directory node without transferring
+//! certificates which are already known.
+//!
+//! 1. A node sends the digest of its warm table in one or more SYNC_DIGEST packets.
+//!    The last packet has the `LAST` flag set.
+//! 2. The directory node answers with UPDATE packets for all certificates which are
+//!    missing in the digest or newer than the listed version,
+//!    followed by a SYNC_DONE packet.
+//!
+//! Two directory nodes synchronize in both directions by both sending their digest.
+//!
+//! ## SYNC_DIGEST
+//!
+//! | Type    | Name                      |
+//! | ------- | ------------------------- |
+//! | u8      | Flags                     |
+//! | n x 48  | Entries                   |
+//!
+//! | Flag | Name | Description                           |
+//! | ---- | ---- | ------------------------------------- |
+//! | 0x01 | LAST | last packet of the digest             |
+//!
+//! Each entry describes the stored certificate of a node:
+//!
+//! | Type | Name                                    |
+//! | ---- | --------------------------------------- |
+//! | 32   | NodeId                                  |
+//! | i64  | `not_before` in seconds since the epoch |
+//! | u64  | sequence number                         |
+//!
+//! ## SYNC_DONE
+//!
+//! | Type | Name                                 |
+//! | ---- | ------------------------------------ |
+//! | u32  | Number of sent UPDATE packets        |
+
+use crate::certificate::CertificateVersion;
+use crate::data::{NodeId, NODE_ID_BYTES};
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::{PacketPayload, PacketType, MAX_PAYLOAD_LENGTH};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use chrono::{TimeZone, Utc};
+use std::convert::TryFrom;
+
+/// Flag of the last SYNC_DIGEST packet of a digest
+pub const SYNC_DIGEST_LAST: u8 = 0x01;
+
+/// Length of a single digest entry in bytes
+pub const DIGEST_ENTRY_LENGTH: usize = NODE_ID_BYTES + 8 + 8;
+
+/// Maximum number of entries fitting into a single SYNC_DIGEST packet
+pub const MAX_DIGEST_ENTRIES: usize = (MAX_PAYLOAD_LENGTH - 1) / DIGEST_ENTRY_LENGTH;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DigestEntry {
+    pub node_id: NodeId,
+    pub version: CertificateVersion,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct SyncDigestPacket {
+    pub last: bool,
+    pub entries: Vec<DigestEntry>,
+}
+
+impl SyncDigestPacket {
+    /// Splits a digest into packets of at most [`MAX_DIGEST_ENTRIES`] entries
+    ///
+    /// Always returns at least one packet, so an empty digest can be sent.
+    pub fn split(entries: Vec<DigestEntry>) -> Vec<SyncDigestPacket> {
+        let mut packets: Vec<SyncDigestPacket> = entries
+            .chunks(MAX_DIGEST_ENTRIES)
+            .map(|chunk| SyncDigestPacket {
+                last: false,
+                entries: chunk.to_vec(),
+            })
+            .collect();
+        if packets.is_empty() {
+            packets.push(SyncDigestPacket::default());
+        }
+        if let Some(packet) = packets.last_mut() {
+            packet.last = true;
+        }
+        packets
+    }
+}
+
+impl PacketPayload for SyncDigestPacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u8(if self.last { SYNC_DIGEST_LAST } else { 0 });
+        for entry in &self.entries {
+            dst.put_slice(entry.node_id.as_bytes());
+            dst.put_i64(entry.version.not_before.timestamp());
+            dst.put_u64(entry.version.sequence_number);
+        }
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        let malformed = ProtocolError::MalformedPacket(PacketType::SyncDigest);
+        if payload.remaining() < 1 || (payload.remaining() - 1) % DIGEST_ENTRY_LENGTH != 0 {
+            return Err(malformed);
+        }
+        let last = payload.get_u8() & SYNC_DIGEST_LAST != 0;
+        let mut entries = Vec::with_capacity(payload.remaining() / DIGEST_ENTRY_LENGTH);
+        while payload.has_remaining() {
+            let node_id = NodeId::try_from(&payload.split_to(NODE_ID_BYTES)[..])
+                .map_err(|_err| malformed.clone())?;
+            let not_before = Utc
+                .timestamp_opt(payload.get_i64(), 0)
+                .single()
+                .ok_or_else(|| malformed.clone())?;
+            let sequence_number = payload.get_u64();
+            entries.push(DigestEntry {
+                node_id,
+                version: CertificateVersion {
+                    not_before,
+                    sequence_number,
+                },
+            });
+        }
+        Ok(SyncDigestPacket { last, entries })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SyncDonePacket {
+    /// Number of UPDATE packets sent in response to the digest
+    pub updates: u32,
+}
+
+impl PacketPayload for SyncDonePacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_u32(self.updates);
+        Ok(())
+    }
+
+    fn decode_payload(mut payload: Bytes) -> ProtocolResult<Self> {
+        if payload.remaining() != 4 {
+            return Err(ProtocolError::MalformedPacket(PacketType::SyncDone));
+        }
+        Ok(SyncDonePacket {
+            updates: payload.get_u32(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::CertificateVersion;
+    use crate::data::NodeId;
+    use crate::protocol::error::ProtocolError;
+    use crate::protocol::frame::{Frame, FrameCodec, PacketType};
+    use crate::protocol::sync::{
+        DigestEntry, SyncDigestPacket, SyncDonePacket, MAX_DIGEST_ENTRIES,
+    };
+    use bytes::BytesMut;
+    use chrono::{TimeZone, Utc};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn entry(index: usize) -> DigestEntry {
+        DigestEntry {
+            node_id: NodeId::from([index as u8; 32]),
+            version: CertificateVersion {
+                not_before: Utc.timestamp(1_600_000_000 + index as i64, 0),
+                sequence_number: index as u64,
+            },
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_sync_packets() {
+        let entries: Vec<_> = (0..MAX_DIGEST_ENTRIES + 10).map(entry).collect();
+        let mut testvec: Vec<Frame> = SyncDigestPacket::split(entries)
+            .into_iter()
+            .map(Frame::SyncDigest)
+            .collect();
+        testvec.extend(
+            SyncDigestPacket::split(Vec::new())
+                .into_iter()
+                .map(Frame::SyncDigest),
+        );
+        testvec.push(Frame::SyncDone(SyncDonePacket { updates: 42 }));
+
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        for case in &testvec {
+            codec.encode(case, &mut buf).unwrap();
+        }
+        for case in testvec {
+            assert_eq!(codec.decode(&mut buf).unwrap(), Some(case));
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_split_digest() {
+        let packets = SyncDigestPacket::split((0..MAX_DIGEST_ENTRIES + 1).map(entry).collect());
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].entries.len(), MAX_DIGEST_ENTRIES);
+        assert!(!packets[0].last);
+        assert_eq!(packets[1].entries, vec![entry(MAX_DIGEST_ENTRIES)]);
+        assert!(packets[1].last);
+
+        assert_eq!(
+            SyncDigestPacket::split(Vec::new()),
+            vec![SyncDigestPacket {
+                last: true,
+                entries: Vec::new()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_decode_malformed_digest() {
+        let mut buf = BytesMut::from(&[0x00, 0x02, 0x06, 0x01, 0x00][..]);
+        assert!(matches!(
+            FrameCodec::new().decode(&mut buf),
+            Err(ProtocolError::MalformedPacket(PacketType::SyncDigest))
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+//! UPDATE packet
+//!
+//! Carries the certificate of a node, which is flooded between directory nodes.
+//!
+//! | Type  | Name                         |
+//! | ----- | ---------------------------- |
+//! | bytes | DER encoded X.509 certificate |
+//!
+//! Only the encoding of the certificate is checked while decoding the packet,
+//! the signature and validity are verified when it is inserted into a
+//! [`crate::directory::Store`].
+
+use crate::certificate::RawCertificate;
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::{PacketPayload, PacketType};
+use bytes::{BufMut, Bytes, BytesMut};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UpdatePacket {
+    pub certificate: RawCertificate,
+}
+
+impl UpdatePacket {
+    pub fn new(certificate: RawCertificate) -> Self {
+        UpdatePacket { certificate }
+    }
+}
+
+impl PacketPayload for UpdatePacket {
+    fn encode_payload(&self, dst: &mut BytesMut) -> ProtocolResult<()> {
+        dst.put_slice(self.certificate.der());
+        Ok(())
+    }
+
+    fn decode_payload(payload: Bytes) -> ProtocolResult<Self> {
+        let certificate = RawCertificate::from_der(payload.to_vec());
+        certificate
+            .node_id()
+            .map_err(|_err| ProtocolError::MalformedPacket(PacketType::Update))?;
+        Ok(UpdatePacket { certificate })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::error::ProtocolError;
+    use crate::protocol::frame::{Frame, FrameCodec, PacketType};
+    use crate::protocol::update::UpdatePacket;
+    use crate::test_util;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    #[test]
+    fn test_encode_decode_update_packet() {
+        let certificate = test_util::certificate();
+        let frame = Frame::Update(UpdatePacket::new(certificate));
+
+        let mut codec = FrameCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(&frame, &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(frame));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_decode_malformed_update_packet() {
+        let mut buf = BytesMut::from(&[0x00, 0x03, 0x02, 0x30, 0x01, 0x00][..]);
+        assert!(matches!(
+            FrameCodec::new().decode(&mut buf),
+            Err(ProtocolError::MalformedPacket(PacketType::Update))
+        ));
+    }
+}
@@ -0,0 +1,61 @@
+//! Errors while establishing a session
+
+use crate::certificate::CertificateError;
+use crate::data::NodeId;
+use std::io;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub type SessionResult<T> = Result<T, SessionError>;
+
+#[derive(Error, Debug, Clone)]
+pub enum SessionError {
+    #[error("Unexpected EOF")]
+    UnexpectedEof,
+    #[error("Invalid handshake magic number {0:#06x}")]
+    InvalidMagic(u16),
+    #[error("Malformed handshake packet")]
+    MalformedHandshake,
+    #[error("No common crypto protocol")]
+    NoCommonCryptoProtocol,
+    #[error("Missing additional data field {0}")]
+    MissingField(u16),
+    #[error("Malformed additional data field {0}")]
+    MalformedField(u16),
+    #[error("Invalid signature of the peer")]
+    InvalidSignature,
+    #[error("Negotiation transcript of the peer differs, possible downgrade attack")]
+    TranscriptMismatch,
+    #[error("Key exchange failed")]
+    InvalidKeyExchange,
+    #[error("Malformed key message")]
+    MalformedKeyMessage,
+    #[error("Decryption failed")]
+    DecryptionFailed,
+    #[error("Public key of the peer is required but not known")]
+    UnknownPeerKey,
+    #[error("Public key can not be converted to a key exchange key")]
+    InvalidPublicKey,
+    #[error("Expected peer {expected}, but the certificate belongs to {actual}")]
+    UnexpectedPeer { expected: NodeId, actual: NodeId },
+    #[error("Invalid certificate: {0}")]
+    Certificate(Arc<CertificateError>),
+    #[error("I/O error: {0}")]
+    Io(Arc<io::Error>),
+}
+
+impl From<io::Error> for SessionError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            SessionError::UnexpectedEof
+        } else {
+            SessionError::Io(Arc::new(err))
+        }
+    }
+}
+
+impl From<CertificateError> for SessionError {
+    fn from(err: CertificateError) -> Self {
+        SessionError::Certificate(Arc::new(err))
+    }
+}
@@ -0,0 +1,367 @@
+//! Session handshake packet
+//!
+//! Both sides send a handshake packet to establish a cryptographic connection.
+//! All integers are encoded in network byte order.
+//!
+//! | Type     | Content                                       |
+//! | -------- | --------------------------------------------- |
+//! | u16      | Magic Number 0xf00f                           |
+//! | u8       | len `n` of supported cryptography protocols   |
+//! | `n` x u8 | id of supported cryptography protocol         |
+//! | u8       | len `m` of additional data fields             |
+//! | -        | `m` additional data fields                    |
+//!
+//! Each additional data field is prepended by a header describing its type and length.
+//!
+//! | Type  | Content                     |
+//! | ----- | --------------------------- |
+//! | u16   | id type of additional field |
+//! | u16   | len of field                |
+//! | `len` | content of field            |
+//!
+//! Fields with an unknown id are preserved.
+
+use crate::session::error::{SessionError, SessionResult};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::BTreeSet;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Magic number at the start of every handshake packet
+pub const HANDSHAKE_MAGIC: u16 = 0xf00f;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HandshakePacket {
+    /// Ids of the supported cryptography protocols, in order of preference
+    pub crypto_protocols: Vec<u8>,
+    pub additional_data: Vec<AdditionalDataField>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AdditionalDataField {
+    pub id: u16,
+    pub content: Bytes,
+}
+
+impl AdditionalDataField {
+    pub fn new(id: u16, content: impl Into<Bytes>) -> Self {
+        AdditionalDataField {
+            id,
+            content: content.into(),
+        }
+    }
+}
+
+impl HandshakePacket {
+    pub fn new(crypto_protocols: Vec<u8>) -> Self {
+        HandshakePacket {
+            crypto_protocols,
+            additional_data: Vec::new(),
+        }
+    }
+
+    /// Content of the first additional data field with the given id
+    pub fn field(&self, id: u16) -> Option<&Bytes> {
+        self.additional_data
+            .iter()
+            .find(|field| field.id == id)
+            .map(|field| &field.content)
+    }
+
+    fn validate(&self) -> SessionResult<()> {
+        let unique: BTreeSet<u8> = self.crypto_protocols.iter().copied().collect();
+        if self.crypto_protocols.is_empty()
+            || self.crypto_protocols.len() > u8::MAX as usize
+            || unique.len() != self.crypto_protocols.len()
+            || self.additional_data.len() > u8::MAX as usize
+            || self
+                .additional_data
+                .iter()
+                .any(|field| field.content.len() > u16::MAX as usize)
+        {
+            return Err(SessionError::MalformedHandshake);
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) -> SessionResult<()> {
+        self.validate()?;
+        dst.put_u16(HANDSHAKE_MAGIC);
+        dst.put_u8(self.crypto_protocols.len() as u8);
+        dst.put_slice(&self.crypto_protocols);
+        dst.put_u8(self.additional_data.len() as u8);
+        for field in &self.additional_data {
+            dst.put_u16(field.id);
+            dst.put_u16(field.content.len() as u16);
+            dst.put_slice(&field.content);
+        }
+        Ok(())
+    }
+
+    /// Decodes a handshake packet from the start of `src`
+    ///
+    /// Returns `None` if `src` does not contain a complete packet yet.
+    /// On success the packet is removed from `src`.
+    pub fn decode(src: &mut BytesMut) -> SessionResult<Option<Self>> {
+        let length = match Self::scan(src)? {
+            Scan::Complete(length) => length,
+            Scan::Incomplete(_) => return Ok(None),
+        };
+        let mut buf = src.split_to(length).freeze();
+
+        buf.advance(2);
+        let protocol_count = buf.get_u8() as usize;
+        let crypto_protocols = buf.split_to(protocol_count).to_vec();
+        let field_count = buf.get_u8() as usize;
+        let mut additional_data = Vec::with_capacity(field_count);
+        for _ in 0..field_count {
+            let id = buf.get_u16();
+            let field_length = buf.get_u16() as usize;
+            let content = buf.split_to(field_length);
+            additional_data.push(AdditionalDataField { id, content });
+        }
+
+        let packet = HandshakePacket {
+            crypto_protocols,
+            additional_data,
+        };
+        packet.validate()?;
+        Ok(Some(packet))
+    }
+
+    /// Scans the packet at the start of `src`, without consuming it
+    fn scan(src: &[u8]) -> SessionResult<Scan> {
+        let mut buf = src;
+        let need = |buf: &[u8], length: usize| Scan::Incomplete(length - buf.len());
+        if buf.remaining() < 2 {
+            return Ok(need(buf, 2));
+        }
+        let magic = buf.get_u16();
+        if magic != HANDSHAKE_MAGIC {
+            return Err(SessionError::InvalidMagic(magic));
+        }
+        if buf.remaining() < 1 {
+            return Ok(need(buf, 1));
+        }
+        let protocol_count = buf.get_u8() as usize;
+        if buf.remaining() < protocol_count + 1 {
+            return Ok(need(buf, protocol_count + 1));
+        }
+        buf.advance(protocol_count);
+        let field_count = buf.get_u8();
+        for _ in 0..field_count {
+            if buf.remaining() < 4 {
+                return Ok(need(buf, 4));
+            }
+            buf.advance(2);
+            let field_length = buf.get_u16() as usize;
+            if buf.remaining() < field_length {
+                return Ok(need(buf, field_length));
+            }
+            buf.advance(field_length);
+        }
+        Ok(Scan::Complete(src.len() - buf.len()))
+    }
+}
+
+/// Result of scanning a possibly incomplete packet
+enum Scan {
+    /// Length of the complete packet
+    Complete(usize),
+    /// Minimal number of bytes needed to continue scanning
+    Incomplete(usize),
+}
+
+/// Writes a handshake packet to a stream
+pub async fn write_handshake<T>(stream: &mut T, packet: &HandshakePacket) -> SessionResult<()>
+where
+    T: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = BytesMut::new();
+    packet.encode(&mut buf)?;
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Reads a handshake packet from a stream
+///
+/// Does not read any data after the end of the packet.
+pub async fn read_handshake<T>(stream: &mut T) -> SessionResult<HandshakePacket>
+where
+    T: AsyncRead + Unpin + ?Sized,
+{
+    let mut buf = BytesMut::new();
+    loop {
+        // only the bytes needed for the next step are read,
+        // so no data after the end of the packet is consumed
+        let missing = match HandshakePacket::scan(&buf)? {
+            Scan::Complete(_) => break,
+            Scan::Incomplete(missing) => missing,
+        };
+        let start = buf.len();
+        buf.resize(start + missing, 0);
+        stream.read_exact(&mut buf[start..]).await?;
+    }
+    Ok(HandshakePacket::decode(&mut buf)?.expect("complete handshake packet"))
+}
+
+/// Encoder and decoder for [`HandshakePacket`]s
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandshakeCodec;
+
+impl Decoder for HandshakeCodec {
+    type Item = HandshakePacket;
+    type Error = SessionError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> SessionResult<Option<HandshakePacket>> {
+        HandshakePacket::decode(src)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> SessionResult<Option<HandshakePacket>> {
+        match self.decode(buf)? {
+            Some(packet) => Ok(Some(packet)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(SessionError::UnexpectedEof),
+        }
+    }
+}
+
+impl Encoder<&HandshakePacket> for HandshakeCodec {
+    type Error = SessionError;
+
+    fn encode(&mut self, item: &HandshakePacket, dst: &mut BytesMut) -> SessionResult<()> {
+        item.encode(dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::error::SessionError;
+    use crate::session::handshake::{
+        read_handshake, write_handshake, AdditionalDataField, HandshakeCodec, HandshakePacket,
+    };
+    use bytes::{BufMut, Bytes, BytesMut};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::Decoder;
+
+    #[test]
+    fn test_encode_decode_handshake() {
+        let testvec = vec![
+            HandshakePacket::new(vec![1]),
+            HandshakePacket {
+                crypto_protocols: vec![2, 1],
+                additional_data: vec![
+                    AdditionalDataField::new(1, vec![0x42; 32]),
+                    AdditionalDataField::new(0xbeef, Bytes::from_static(b"unknown")),
+                    AdditionalDataField::new(3, Bytes::new()),
+                ],
+            },
+        ];
+
+        for case in testvec {
+            let mut encoded = BytesMut::new();
+            case.encode(&mut encoded).unwrap();
+            encoded.put_slice(b"trailing");
+            let decoded = HandshakePacket::decode(&mut encoded).unwrap();
+            assert_eq!(decoded, Some(case));
+            assert_eq!(&encoded[..], b"trailing");
+        }
+    }
+
+    #[test]
+    fn test_encode_wire_format() {
+        let packet = HandshakePacket {
+            crypto_protocols: vec![1],
+            additional_data: vec![AdditionalDataField::new(2, vec![0xaa])],
+        };
+        let mut encoded = BytesMut::new();
+        packet.encode(&mut encoded).unwrap();
+        assert_eq!(
+            &encoded[..],
+            &[0xf0, 0x0f, 0x01, 0x01, 0x01, 0x00, 0x02, 0x00, 0x01, 0xaa]
+        );
+    }
+
+    #[test]
+    fn test_decode_partial() {
+        let packet = HandshakePacket {
+            crypto_protocols: vec![1, 2],
+            additional_data: vec![AdditionalDataField::new(1, vec![0x42; 10])],
+        };
+        let mut encoded = BytesMut::new();
+        packet.encode(&mut encoded).unwrap();
+
+        for length in 0..encoded.len() {
+            let mut partial = BytesMut::from(&encoded[..length]);
+            assert_eq!(HandshakeCodec.decode(&mut partial).unwrap(), None);
+            assert_eq!(partial.len(), length);
+            if length > 0 {
+                assert!(matches!(
+                    HandshakeCodec.decode_eof(&mut partial),
+                    Err(SessionError::UnexpectedEof)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut buf = BytesMut::from(&[0x0f, 0xf0, 0x01, 0x01, 0x00][..]);
+        assert!(matches!(
+            HandshakePacket::decode(&mut buf),
+            Err(SessionError::InvalidMagic(0x0ff0))
+        ));
+
+        let testvec: Vec<&[u8]> = vec![
+            // no crypto protocols
+            &[0xf0, 0x0f, 0x00, 0x00],
+            // duplicate crypto protocols
+            &[0xf0, 0x0f, 0x02, 0x01, 0x01, 0x00],
+        ];
+        for case in testvec {
+            assert!(matches!(
+                HandshakePacket::decode(&mut BytesMut::from(case)),
+                Err(SessionError::MalformedHandshake)
+            ));
+        }
+
+        let mut buf = BytesMut::new();
+        assert!(matches!(
+            HandshakePacket::new(vec![]).encode(&mut buf),
+            Err(SessionError::MalformedHandshake)
+        ));
+        assert!(matches!(
+            HandshakePacket {
+                crypto_protocols: vec![1],
+                additional_data: vec![AdditionalDataField::new(1, vec![0; 0x10000])],
+            }
+            .encode(&mut buf),
+            Err(SessionError::MalformedHandshake)
+        ));
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_write_handshake() {
+        let packet = HandshakePacket {
+            crypto_protocols: vec![1, 2],
+            additional_data: vec![AdditionalDataField::new(1, vec![0x42; 100])],
+        };
+        let (mut a, mut b) = tokio::io::duplex(16);
+
+        let writer = async {
+            write_handshake(&mut a, &packet).await.unwrap();
+            a.write_all(b"next").await.unwrap();
+        };
+        let reader = async {
+            let received = read_handshake(&mut b).await.unwrap();
+            let mut next = [0u8; 4];
+            b.read_exact(&mut next).await.unwrap();
+            (received, next)
+        };
+        let ((), (received, next)) = tokio::join!(writer, reader);
+        assert_eq!(received, packet);
+        assert_eq!(&next, b"next");
+    }
+}
@@ -0,0 +1,68 @@
+use crate::data::NodeId;
+use sodiumoxide::crypto::sign;
+use std::fmt;
+
+/// PKCS#8 v1 header of an Ed25519 private key, followed by the 32 byte seed
+const PKCS8_ED25519_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// Long term signing key pair of a node
+///
+/// The [`NodeId`] of a node is derived from the public key.
+#[derive(Clone)]
+pub struct Identity {
+    public_key: sign::PublicKey,
+    secret_key: sign::SecretKey,
+}
+
+impl Identity {
+    /// Generates a new random identity
+    pub fn generate() -> Self {
+        crate::session::init();
+        let (public_key, secret_key) = sign::gen_keypair();
+        Identity {
+            public_key,
+            secret_key,
+        }
+    }
+
+    /// Derives the identity from a 32 byte Ed25519 seed
+    pub fn from_seed(seed: &[u8; sign::SEEDBYTES]) -> Self {
+        crate::session::init();
+        let (public_key, secret_key) = sign::keypair_from_seed(&sign::Seed(*seed));
+        Identity {
+            public_key,
+            secret_key,
+        }
+    }
+
+    /// Encodes the secret key as PKCS#8 v1 DER
+    ///
+    /// Used to sign the certificate of the node with its session identity.
+    pub fn to_pkcs8_der(&self) -> Vec<u8> {
+        let mut der = PKCS8_ED25519_PREFIX.to_vec();
+        der.extend_from_slice(&self.secret_key.as_ref()[..sign::SEEDBYTES]);
+        der
+    }
+
+    pub fn public_key(&self) -> &sign::PublicKey {
+        &self.public_key
+    }
+
+    pub(crate) fn secret_key(&self) -> &sign::SecretKey {
+        &self.secret_key
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        NodeId::from_public_key(self.public_key.as_ref())
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("node_id", &self.node_id())
+            .finish()
+    }
+}
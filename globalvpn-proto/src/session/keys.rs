@@ -0,0 +1,47 @@
+use crate::data::NodeId;
+use sodiumoxide::crypto::sign;
+use sodiumoxide::utils::memzero;
+use std::fmt;
+
+/// Length of a symmetric session key in bytes
+pub const SESSION_KEY_BYTES: usize = 32;
+
+/// Side of the connection
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Role {
+    /// The node which opened the connection
+    Initiator,
+    /// The node which accepted the connection
+    Responder,
+}
+
+/// Symmetric key for a single direction of a session
+///
+/// The key is zeroed out when it goes out of scope.
+#[derive(Clone, Eq, PartialEq)]
+pub struct SessionKey(pub [u8; SESSION_KEY_BYTES]);
+
+impl Drop for SessionKey {
+    fn drop(&mut self) {
+        memzero(&mut self.0);
+    }
+}
+
+impl fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionKey(****)")
+    }
+}
+
+/// Result of a successful key exchange
+#[derive(Debug, Clone)]
+pub struct SessionKeys {
+    /// NodeId of the authenticated peer
+    pub peer: NodeId,
+    /// Public signing key of the authenticated peer
+    pub peer_public_key: sign::PublicKey,
+    /// Key for decrypting received data
+    pub rx: SessionKey,
+    /// Key for encrypting sent data
+    pub tx: SessionKey,
+}
@@ -0,0 +1,28 @@
+//! Cryptographic sessions between nodes
+//!
+//! A session starts with both sides sending a [`handshake::HandshakePacket`],
+//! which announces the supported cryptography protocols.
+//! The strongest common protocol then establishes the session keys,
+//! see [`suite::establish`].
+
+pub mod error;
+pub mod handshake;
+pub mod identity;
+pub mod keys;
+pub mod noise;
+pub mod sodium;
+pub mod stream;
+pub mod suite;
+
+pub use identity::Identity;
+pub use keys::{Role, SessionKey, SessionKeys};
+pub use noise::NoiseIkSuite;
+pub use stream::{RekeyPolicy, SecureStream};
+pub use suite::{establish, CryptoSuite, CryptoSuites};
+
+/// Initializes libsodium
+///
+/// Can be called multiple times, all functions of this module call it before using libsodium.
+pub fn init() {
+    sodiumoxide::init().expect("libsodium could not be initialized");
+}
@@ -0,0 +1,516 @@
+//! Noise IK handshake (crypto protocol 2)
+//!
+//! Uses the `Noise_IK_25519_ChaChaPoly_BLAKE2s` protocol.
+//! The static Noise keys are the X25519 equivalents of the Ed25519 signing keys,
+//! so no additional key material has to be distributed.
+//!
+//! The initiator has to know the public key of the responder in advance,
+//! usually from the certificate of the responder.
+//! In return the suite starts early, see [`crate::session::suite`],
+//! so the session is established in a single round trip,
+//! and the identity of the initiator is only revealed to the responder.
+//!
+//! 1. The initiator sends the first Noise message right after its handshake packet.
+//!    Its payload is the Ed25519 public key of the initiator.
+//! 2. The responder checks that the public key matches the static Noise key of the
+//!    initiator and replies with the second Noise message after its handshake packet.
+//!    Its payload is the SHA-256 hash of the negotiation transcript.
+//! 3. The initiator checks the transcript hash.
+//!
+//! The encoded handshake packet of the initiator is used as the Noise prologue.
+//! If the suite is selected without being started early,
+//! the negotiation transcript is used as the Noise prologue and the payload of the
+//! second Noise message is empty.
+//! Each Noise message is prepended with its length as u16.
+
+use crate::certificate::{CertificateData, RawCertificate, ValidityPolicy};
+use crate::data::NodeId;
+use crate::session::error::{SessionError, SessionResult};
+use crate::session::identity::Identity;
+use crate::session::keys::{Role, SessionKey, SessionKeys};
+use crate::session::suite::{CryptoSuite, EarlyHandshake, SessionIo};
+use futures::future::BoxFuture;
+use snow::{Builder, HandshakeState};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Id of the Noise IK crypto protocol
+pub const CRYPTO_PROTOCOL_NOISE_IK: u8 = 2;
+
+/// Noise protocol name
+pub const NOISE_IK_PARAMS: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
+/// Maximum length of a Noise message
+const MAX_NOISE_MESSAGE_LENGTH: usize = u16::MAX as usize;
+
+/// Noise IK handshake
+///
+/// Responders can always use the suite,
+/// initiators only if the public key of the responder is known.
+#[derive(Debug, Clone, Default)]
+pub struct NoiseIkSuite {
+    remote_public_key: Option<sign::PublicKey>,
+}
+
+impl NoiseIkSuite {
+    /// Creates the suite without knowledge about the peer,
+    /// which is only usable as responder
+    pub fn new() -> Self {
+        NoiseIkSuite {
+            remote_public_key: None,
+        }
+    }
+
+    /// Creates the suite for connecting to the node with the given public signing key
+    pub fn with_remote_public_key(remote_public_key: sign::PublicKey) -> Self {
+        NoiseIkSuite {
+            remote_public_key: Some(remote_public_key),
+        }
+    }
+
+    /// Creates the suite for connecting to `peer`, using its verified certificate
+    ///
+    /// Fails if the certificate belongs to a different node.
+    pub fn with_remote_certificate(
+        certificate: &RawCertificate,
+        peer: &NodeId,
+    ) -> SessionResult<Self> {
+        CertificateData::decode(certificate, &ValidityPolicy::default())?;
+        let actual = certificate.node_id()?;
+        if actual != *peer {
+            return Err(SessionError::UnexpectedPeer {
+                expected: *peer,
+                actual,
+            });
+        }
+        let public_key = sign::PublicKey::from_slice(&certificate.public_key()?)
+            .ok_or(SessionError::InvalidPublicKey)?;
+        Ok(NoiseIkSuite::with_remote_public_key(public_key))
+    }
+
+    fn remote_public_key(&self) -> SessionResult<&sign::PublicKey> {
+        self.remote_public_key
+            .as_ref()
+            .ok_or(SessionError::UnknownPeerKey)
+    }
+}
+
+impl CryptoSuite for NoiseIkSuite {
+    fn id(&self) -> u8 {
+        CRYPTO_PROTOCOL_NOISE_IK
+    }
+
+    fn strength(&self) -> u8 {
+        20
+    }
+
+    fn supports(&self, role: Role) -> bool {
+        role == Role::Responder || self.remote_public_key.is_some()
+    }
+
+    fn establish<'a>(
+        &'a self,
+        io: &'a mut dyn SessionIo,
+        identity: &'a Identity,
+        role: Role,
+        transcript: &'a [u8],
+    ) -> BoxFuture<'a, SessionResult<SessionKeys>> {
+        Box::pin(async move {
+            match role {
+                Role::Initiator => {
+                    noise_ik_initiator(io, identity, self.remote_public_key()?, transcript).await
+                }
+                Role::Responder => noise_ik_responder(io, identity, transcript).await,
+            }
+        })
+    }
+
+    fn starts_early(&self) -> bool {
+        self.remote_public_key.is_some()
+    }
+
+    fn start_early(
+        &self,
+        identity: &Identity,
+        handshake: &[u8],
+    ) -> SessionResult<(Vec<u8>, Box<dyn EarlyHandshake>)> {
+        let remote_public_key = *self.remote_public_key()?;
+        let mut handshake = initiator_state(identity, &remote_public_key, handshake)?;
+        let message = encrypt_message(&mut handshake, identity.public_key().as_ref())?;
+        let early = NoiseIkEarly {
+            handshake,
+            remote_public_key,
+        };
+        Ok((message, Box::new(early)))
+    }
+
+    fn establish_early<'a>(
+        &'a self,
+        io: &'a mut dyn SessionIo,
+        identity: &'a Identity,
+        message: &'a [u8],
+        handshake: &'a [u8],
+        transcript: &'a [u8],
+    ) -> BoxFuture<'a, SessionResult<SessionKeys>> {
+        Box::pin(async move {
+            let mut handshake = responder_state(identity, handshake)?;
+            let payload = decrypt_message(&mut handshake, message)?;
+            let peer_public_key = initiator_public_key(&handshake, &payload)?;
+            let transcript_hash = sha256::hash(transcript);
+            write_message(io, &mut handshake, transcript_hash.as_ref()).await?;
+            session_keys(handshake, Role::Responder, peer_public_key)
+        })
+    }
+}
+
+/// Initiator waiting for the second Noise message of an early started handshake
+struct NoiseIkEarly {
+    handshake: HandshakeState,
+    remote_public_key: sign::PublicKey,
+}
+
+impl EarlyHandshake for NoiseIkEarly {
+    fn finish<'a>(
+        self: Box<Self>,
+        io: &'a mut dyn SessionIo,
+        transcript: &'a [u8],
+    ) -> BoxFuture<'a, SessionResult<SessionKeys>> {
+        Box::pin(async move {
+            let NoiseIkEarly {
+                mut handshake,
+                remote_public_key,
+            } = *self;
+            let payload = read_message(io, &mut handshake).await?;
+            if payload != sha256::hash(transcript).as_ref() {
+                return Err(SessionError::TranscriptMismatch);
+            }
+            session_keys(handshake, Role::Initiator, remote_public_key)
+        })
+    }
+}
+
+/// Runs the initiator side of the Noise IK handshake over a stream
+pub async fn noise_ik_initiator<T>(
+    stream: &mut T,
+    identity: &Identity,
+    remote_public_key: &sign::PublicKey,
+    transcript: &[u8],
+) -> SessionResult<SessionKeys>
+where
+    T: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let mut handshake = initiator_state(identity, remote_public_key, transcript)?;
+    write_message(stream, &mut handshake, identity.public_key().as_ref()).await?;
+    let payload = read_message(stream, &mut handshake).await?;
+    if !payload.is_empty() {
+        return Err(SessionError::MalformedHandshake);
+    }
+
+    session_keys(handshake, Role::Initiator, *remote_public_key)
+}
+
+/// Runs the responder side of the Noise IK handshake over a stream
+pub async fn noise_ik_responder<T>(
+    stream: &mut T,
+    identity: &Identity,
+    transcript: &[u8],
+) -> SessionResult<SessionKeys>
+where
+    T: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let mut handshake = responder_state(identity, transcript)?;
+    let payload = read_message(stream, &mut handshake).await?;
+    let peer_public_key = initiator_public_key(&handshake, &payload)?;
+    write_message(stream, &mut handshake, &[]).await?;
+
+    session_keys(handshake, Role::Responder, peer_public_key)
+}
+
+fn initiator_state(
+    identity: &Identity,
+    remote_public_key: &sign::PublicKey,
+    prologue: &[u8],
+) -> SessionResult<HandshakeState> {
+    let local_private_key = x25519_secret_key(identity)?;
+    let remote_static = x25519_public_key(remote_public_key)?;
+    Builder::new(NOISE_IK_PARAMS.parse().expect("invalid Noise params"))
+        .local_private_key(&local_private_key)
+        .remote_public_key(&remote_static)
+        .prologue(prologue)
+        .build_initiator()
+        .map_err(|_err| SessionError::InvalidKeyExchange)
+}
+
+fn responder_state(identity: &Identity, prologue: &[u8]) -> SessionResult<HandshakeState> {
+    let local_private_key = x25519_secret_key(identity)?;
+    Builder::new(NOISE_IK_PARAMS.parse().expect("invalid Noise params"))
+        .local_private_key(&local_private_key)
+        .prologue(prologue)
+        .build_responder()
+        .map_err(|_err| SessionError::InvalidKeyExchange)
+}
+
+/// Checks that the payload of the first Noise message matches the static key of the initiator
+fn initiator_public_key(
+    handshake: &HandshakeState,
+    payload: &[u8],
+) -> SessionResult<sign::PublicKey> {
+    let peer_public_key =
+        sign::PublicKey::from_slice(payload).ok_or(SessionError::MalformedHandshake)?;
+    let remote_static = handshake
+        .get_remote_static()
+        .ok_or(SessionError::InvalidKeyExchange)?;
+    if x25519_public_key(&peer_public_key)? != remote_static {
+        return Err(SessionError::InvalidSignature);
+    }
+    Ok(peer_public_key)
+}
+
+fn x25519_secret_key(identity: &Identity) -> SessionResult<Vec<u8>> {
+    sign::to_curve25519_sk(identity.secret_key())
+        .map(|key| key.as_ref().to_vec())
+        .map_err(|()| SessionError::InvalidPublicKey)
+}
+
+fn x25519_public_key(public_key: &sign::PublicKey) -> SessionResult<Vec<u8>> {
+    sign::to_curve25519_pk(public_key)
+        .map(|key| key.as_ref().to_vec())
+        .map_err(|()| SessionError::InvalidPublicKey)
+}
+
+async fn write_message<T>(
+    stream: &mut T,
+    handshake: &mut HandshakeState,
+    payload: &[u8],
+) -> SessionResult<()>
+where
+    T: AsyncWrite + Unpin + ?Sized,
+{
+    let message = encrypt_message(handshake, payload)?;
+    stream.write_u16(message.len() as u16).await?;
+    stream.write_all(&message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_message<T>(stream: &mut T, handshake: &mut HandshakeState) -> SessionResult<Vec<u8>>
+where
+    T: AsyncRead + Unpin + ?Sized,
+{
+    let length = stream.read_u16().await? as usize;
+    let mut message = vec![0u8; length];
+    stream.read_exact(&mut message).await?;
+    decrypt_message(handshake, &message)
+}
+
+fn encrypt_message(handshake: &mut HandshakeState, payload: &[u8]) -> SessionResult<Vec<u8>> {
+    let mut message = vec![0u8; MAX_NOISE_MESSAGE_LENGTH];
+    let length = handshake
+        .write_message(payload, &mut message)
+        .map_err(|_err| SessionError::InvalidKeyExchange)?;
+    message.truncate(length);
+    Ok(message)
+}
+
+fn decrypt_message(handshake: &mut HandshakeState, message: &[u8]) -> SessionResult<Vec<u8>> {
+    let mut payload = vec![0u8; MAX_NOISE_MESSAGE_LENGTH];
+    let length = handshake
+        .read_message(message, &mut payload)
+        .map_err(|_err| SessionError::DecryptionFailed)?;
+    payload.truncate(length);
+    Ok(payload)
+}
+
+fn session_keys(
+    mut handshake: HandshakeState,
+    role: Role,
+    peer_public_key: sign::PublicKey,
+) -> SessionResult<SessionKeys> {
+    if !handshake.is_handshake_finished() {
+        return Err(SessionError::InvalidKeyExchange);
+    }
+    let (initiator_to_responder, responder_to_initiator) = handshake.dangerously_get_raw_split();
+    let (rx, tx) = match role {
+        Role::Initiator => (responder_to_initiator, initiator_to_responder),
+        Role::Responder => (initiator_to_responder, responder_to_initiator),
+    };
+    Ok(SessionKeys {
+        peer: NodeId::from_public_key(peer_public_key.as_ref()),
+        peer_public_key,
+        rx: SessionKey(rx),
+        tx: SessionKey(tx),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::certificate::{
+        CertificateData, NodeMetadata, NodeReachabilityInformation, RawCertificate,
+    };
+    use crate::session::error::SessionError;
+    use crate::session::handshake::{read_handshake, write_handshake, HandshakePacket};
+    use crate::session::identity::Identity;
+    use crate::session::keys::Role;
+    use crate::session::noise::{noise_ik_initiator, noise_ik_responder, NoiseIkSuite};
+    use crate::session::suite::{
+        establish, CryptoSuite, CryptoSuites, SodiumSuite, FIELD_EARLY_START,
+    };
+    use bytes::BytesMut;
+    use tokio::io::AsyncReadExt;
+
+    fn certificate(identity: &Identity) -> RawCertificate {
+        CertificateData {
+            reachability: NodeReachabilityInformation::default(),
+            metadata: NodeMetadata::default(),
+        }
+        .sign(&identity.to_pkcs8_der())
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_noise_ik() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let (a_keys, b_keys) = tokio::join!(
+            noise_ik_initiator(&mut a, &a_identity, b_identity.public_key(), b"transcript"),
+            noise_ik_responder(&mut b, &b_identity, b"transcript"),
+        );
+        let (a_keys, b_keys) = (a_keys.unwrap(), b_keys.unwrap());
+        assert_eq!(a_keys.peer, b_identity.node_id());
+        assert_eq!(b_keys.peer, a_identity.node_id());
+        assert_eq!(a_keys.tx, b_keys.rx);
+        assert_eq!(a_keys.rx, b_keys.tx);
+        assert_ne!(a_keys.tx, a_keys.rx);
+    }
+
+    #[tokio::test]
+    async fn test_reject_wrong_responder_key() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let responder = async {
+            let result = noise_ik_responder(&mut b, &b_identity, &[]).await;
+            drop(b);
+            result
+        };
+        let other = Identity::generate();
+        let (a_keys, b_keys) = tokio::join!(
+            noise_ik_initiator(&mut a, &a_identity, other.public_key(), &[]),
+            responder,
+        );
+        assert!(matches!(b_keys, Err(SessionError::DecryptionFailed)));
+        assert!(a_keys.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reject_transcript_mismatch() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let responder = async {
+            let result = noise_ik_responder(&mut b, &b_identity, b"responder").await;
+            drop(b);
+            result
+        };
+        let (a_keys, b_keys) = tokio::join!(
+            noise_ik_initiator(&mut a, &a_identity, b_identity.public_key(), b"initiator"),
+            responder,
+        );
+        assert!(matches!(b_keys, Err(SessionError::DecryptionFailed)));
+        assert!(a_keys.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_with_certificate() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let certificate = certificate(&b_identity);
+        assert_eq!(certificate.node_id().unwrap(), b_identity.node_id());
+
+        let a_suites = CryptoSuites::default().with(
+            NoiseIkSuite::with_remote_certificate(&certificate, &b_identity.node_id()).unwrap(),
+        );
+        let b_suites = CryptoSuites::default();
+        assert_eq!(a_suites.ids(Role::Initiator), vec![2, 1]);
+        assert_eq!(CryptoSuites::default().ids(Role::Initiator), vec![1]);
+        assert_eq!(b_suites.ids(Role::Responder), vec![2, 1]);
+
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (a_keys, b_keys) = tokio::join!(
+            establish(&mut a, &a_identity, Role::Initiator, &a_suites),
+            establish(&mut b, &b_identity, Role::Responder, &b_suites),
+        );
+        let (a_keys, b_keys) = (a_keys.unwrap(), b_keys.unwrap());
+        assert_eq!(a_keys.peer, b_identity.node_id());
+        assert_eq!(b_keys.peer, a_identity.node_id());
+        assert_eq!(a_keys.tx, b_keys.rx);
+    }
+
+    #[test]
+    fn test_reject_certificate_of_other_peer() {
+        let (b_identity, other) = (Identity::generate(), Identity::generate());
+        let result =
+            NoiseIkSuite::with_remote_certificate(&certificate(&other), &b_identity.node_id());
+        assert!(matches!(
+            result,
+            Err(SessionError::UnexpectedPeer { expected, actual })
+                if expected == b_identity.node_id() && actual == other.node_id()
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_single_round_trip() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let a_suites = CryptoSuites::default().with(NoiseIkSuite::with_remote_public_key(
+            *b_identity.public_key(),
+        ));
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let responder = async {
+            // the first Noise message arrives before the responder sent anything
+            let initiator = read_handshake(&mut b).await.unwrap();
+            assert_eq!(initiator.field(FIELD_EARLY_START).unwrap().as_ref(), &[2]);
+            let mut message = vec![0u8; b.read_u16().await.unwrap() as usize];
+            b.read_exact(&mut message).await.unwrap();
+
+            let local = HandshakePacket::new(vec![2, 1]);
+            write_handshake(&mut b, &local).await.unwrap();
+            let (mut handshake, mut transcript) = (BytesMut::new(), BytesMut::new());
+            initiator.encode(&mut handshake).unwrap();
+            transcript.extend_from_slice(&handshake);
+            local.encode(&mut transcript).unwrap();
+            NoiseIkSuite::new()
+                .establish_early(&mut b, &b_identity, &message, &handshake, &transcript)
+                .await
+        };
+        let (a_keys, b_keys) = tokio::join!(
+            establish(&mut a, &a_identity, Role::Initiator, &a_suites),
+            responder,
+        );
+        let (a_keys, b_keys) = (a_keys.unwrap(), b_keys.unwrap());
+        assert_eq!(a_keys.peer, b_identity.node_id());
+        assert_eq!(b_keys.peer, a_identity.node_id());
+        assert_eq!(a_keys.tx, b_keys.rx);
+        assert_eq!(a_keys.rx, b_keys.tx);
+    }
+
+    #[tokio::test]
+    async fn test_early_start_falls_back() {
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let a_suites = CryptoSuites::default().with(NoiseIkSuite::with_remote_public_key(
+            *b_identity.public_key(),
+        ));
+        // the responder does not support Noise IK and discards the early message
+        let b_suites = CryptoSuites::new().with(SodiumSuite);
+
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (a_keys, b_keys) = tokio::join!(
+            establish(&mut a, &a_identity, Role::Initiator, &a_suites),
+            establish(&mut b, &b_identity, Role::Responder, &b_suites),
+        );
+        let (a_keys, b_keys) = (a_keys.unwrap(), b_keys.unwrap());
+        assert_eq!(a_keys.peer, b_identity.node_id());
+        assert_eq!(a_keys.tx, b_keys.rx);
+    }
+}
@@ -0,0 +1,299 @@
+//! Sodium key exchange (crypto protocol 1)
+//!
+//! 1. Both sides send a handshake packet containing their public signing key
+//!    (additional data field 1) and their `crypto_kx` public key followed by the
+//!    SHA-256 hash of the negotiation transcript, signed with `crypto_sign`
+//!    (additional data field 2).
+//! 2. Both sides verify the signed key exchange key and transcript hash of the peer
+//!    and derive `crypto_kx` session keys.
+//!    The initiator uses the client, the responder the server session keys.
+//! 3. Both sides send a random symmetric key for their sending direction,
+//!    encrypted with `crypto_secretbox` using the transmit key from step 2.
+//!
+//! | Type | Content                                        |
+//! | ---- | ---------------------------------------------- |
+//! | 24   | secretbox nonce                                |
+//! | 48   | secretbox encrypted symmetric key (32 bytes)   |
+
+use crate::data::NodeId;
+use crate::session::error::{SessionError, SessionResult};
+use crate::session::handshake::{
+    read_handshake, write_handshake, AdditionalDataField, HandshakePacket,
+};
+use crate::session::identity::Identity;
+use crate::session::keys::{Role, SessionKey, SessionKeys, SESSION_KEY_BYTES};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::{kx, secretbox, sign};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Id of the Sodium key exchange crypto protocol
+pub const CRYPTO_PROTOCOL_SODIUM: u8 = 1;
+
+/// Additional data field containing the public signing key
+pub const FIELD_SIGNING_PUBLIC_KEY: u16 = 1;
+/// Additional data field containing the signed `crypto_kx` public key and transcript hash
+pub const FIELD_SIGNED_KX_PUBLIC_KEY: u16 = 2;
+
+/// Length of the message containing the encrypted symmetric key
+pub const KEY_MESSAGE_BYTES: usize =
+    secretbox::NONCEBYTES + SESSION_KEY_BYTES + secretbox::MACBYTES;
+
+/// Key exchange waiting for the handshake packet of the peer
+pub struct SodiumKeyExchange {
+    role: Role,
+    transcript_hash: sha256::Digest,
+    public_key: sign::PublicKey,
+    kx_public_key: kx::PublicKey,
+    kx_secret_key: kx::SecretKey,
+    signed_kx_public_key: Vec<u8>,
+}
+
+/// Key exchange waiting for the encrypted symmetric key of the peer
+pub struct SodiumKeyTransfer {
+    peer: NodeId,
+    peer_public_key: sign::PublicKey,
+    kx_rx: kx::SessionKey,
+    tx: SessionKey,
+}
+
+impl SodiumKeyExchange {
+    /// Starts a key exchange
+    ///
+    /// The `transcript` of the crypto protocol negotiation is signed together with
+    /// the key exchange key, so that a downgrade of the negotiation is detected.
+    pub fn new(identity: &Identity, role: Role, transcript: &[u8]) -> Self {
+        crate::session::init();
+        let (kx_public_key, kx_secret_key) = kx::gen_keypair();
+        let transcript_hash = sha256::hash(transcript);
+        let mut message = kx_public_key.as_ref().to_vec();
+        message.extend_from_slice(transcript_hash.as_ref());
+        let signed_kx_public_key = sign::sign(&message, identity.secret_key());
+        SodiumKeyExchange {
+            role,
+            transcript_hash,
+            public_key: *identity.public_key(),
+            kx_public_key,
+            kx_secret_key,
+            signed_kx_public_key,
+        }
+    }
+
+    /// Handshake packet which has to be sent to the peer
+    pub fn handshake(&self) -> HandshakePacket {
+        let mut packet = HandshakePacket::new(vec![CRYPTO_PROTOCOL_SODIUM]);
+        packet.additional_data = vec![
+            AdditionalDataField::new(FIELD_SIGNING_PUBLIC_KEY, self.public_key.as_ref().to_vec()),
+            AdditionalDataField::new(
+                FIELD_SIGNED_KX_PUBLIC_KEY,
+                self.signed_kx_public_key.clone(),
+            ),
+        ];
+        packet
+    }
+
+    /// Verifies the handshake packet of the peer
+    ///
+    /// Returns the next state and the key message which has to be sent to the peer.
+    pub fn receive_handshake(
+        self,
+        peer_handshake: &HandshakePacket,
+    ) -> SessionResult<(SodiumKeyTransfer, Vec<u8>)> {
+        if !peer_handshake
+            .crypto_protocols
+            .contains(&CRYPTO_PROTOCOL_SODIUM)
+        {
+            return Err(SessionError::NoCommonCryptoProtocol);
+        }
+        let field = |id| {
+            peer_handshake
+                .field(id)
+                .ok_or(SessionError::MissingField(id))
+        };
+        let peer_public_key = sign::PublicKey::from_slice(field(FIELD_SIGNING_PUBLIC_KEY)?)
+            .ok_or(SessionError::MalformedField(FIELD_SIGNING_PUBLIC_KEY))?;
+        let message = sign::verify(field(FIELD_SIGNED_KX_PUBLIC_KEY)?, &peer_public_key)
+            .map_err(|()| SessionError::InvalidSignature)?;
+        if message.len() != kx::PUBLICKEYBYTES + sha256::DIGESTBYTES {
+            return Err(SessionError::MalformedField(FIELD_SIGNED_KX_PUBLIC_KEY));
+        }
+        let (peer_kx_public_key, peer_transcript_hash) = message.split_at(kx::PUBLICKEYBYTES);
+        if peer_transcript_hash != self.transcript_hash.as_ref() {
+            return Err(SessionError::TranscriptMismatch);
+        }
+        let peer_kx_public_key = kx::PublicKey::from_slice(peer_kx_public_key)
+            .ok_or(SessionError::MalformedField(FIELD_SIGNED_KX_PUBLIC_KEY))?;
+
+        let (kx_rx, kx_tx) = match self.role {
+            Role::Initiator => kx::client_session_keys(
+                &self.kx_public_key,
+                &self.kx_secret_key,
+                &peer_kx_public_key,
+            ),
+            Role::Responder => kx::server_session_keys(
+                &self.kx_public_key,
+                &self.kx_secret_key,
+                &peer_kx_public_key,
+            ),
+        }
+        .map_err(|()| SessionError::InvalidKeyExchange)?;
+
+        let tx = secretbox::gen_key();
+        let nonce = secretbox::gen_nonce();
+        let mut key_message = nonce.as_ref().to_vec();
+        key_message.extend(secretbox::seal(
+            tx.as_ref(),
+            &nonce,
+            &secretbox::Key(kx_tx.0),
+        ));
+
+        let transfer = SodiumKeyTransfer {
+            peer: NodeId::from_public_key(peer_public_key.as_ref()),
+            peer_public_key,
+            kx_rx,
+            tx: SessionKey(tx.0),
+        };
+        Ok((transfer, key_message))
+    }
+}
+
+impl SodiumKeyTransfer {
+    /// NodeId of the peer, authenticated by the handshake
+    pub fn peer(&self) -> NodeId {
+        self.peer
+    }
+
+    /// Decrypts the key message of the peer
+    pub fn receive_key(self, key_message: &[u8]) -> SessionResult<SessionKeys> {
+        if key_message.len() != KEY_MESSAGE_BYTES {
+            return Err(SessionError::MalformedKeyMessage);
+        }
+        let (nonce, ciphertext) = key_message.split_at(secretbox::NONCEBYTES);
+        let nonce = secretbox::Nonce::from_slice(nonce).ok_or(SessionError::MalformedKeyMessage)?;
+        let rx = secretbox::open(ciphertext, &nonce, &secretbox::Key(self.kx_rx.0))
+            .map_err(|()| SessionError::DecryptionFailed)?;
+        let mut rx_key = [0u8; SESSION_KEY_BYTES];
+        rx_key.copy_from_slice(&rx);
+
+        Ok(SessionKeys {
+            peer: self.peer,
+            peer_public_key: self.peer_public_key,
+            rx: SessionKey(rx_key),
+            tx: self.tx,
+        })
+    }
+}
+
+/// Runs the Sodium key exchange over a stream
+///
+/// `transcript` is the transcript of the crypto protocol negotiation,
+/// see [`crate::session::suite`].
+pub async fn sodium_key_exchange<T>(
+    stream: &mut T,
+    identity: &Identity,
+    role: Role,
+    transcript: &[u8],
+) -> SessionResult<SessionKeys>
+where
+    T: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let exchange = SodiumKeyExchange::new(identity, role, transcript);
+    write_handshake(stream, &exchange.handshake()).await?;
+    let peer_handshake = read_handshake(stream).await?;
+
+    let (transfer, key_message) = exchange.receive_handshake(&peer_handshake)?;
+    stream.write_all(&key_message).await?;
+    stream.flush().await?;
+
+    let mut peer_key_message = [0u8; KEY_MESSAGE_BYTES];
+    stream.read_exact(&mut peer_key_message).await?;
+    transfer.receive_key(&peer_key_message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::error::SessionError;
+    use crate::session::handshake::{write_handshake, AdditionalDataField};
+    use crate::session::identity::Identity;
+    use crate::session::keys::Role;
+    use crate::session::sodium::{
+        sodium_key_exchange, SodiumKeyExchange, FIELD_SIGNED_KX_PUBLIC_KEY,
+    };
+    use sodiumoxide::crypto::{kx, sign};
+
+    #[tokio::test]
+    async fn test_key_exchange() {
+        let initiator = Identity::generate();
+        let responder = Identity::generate();
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let (initiator_keys, responder_keys) = tokio::join!(
+            sodium_key_exchange(&mut a, &initiator, Role::Initiator, b"transcript"),
+            sodium_key_exchange(&mut b, &responder, Role::Responder, b"transcript"),
+        );
+        let initiator_keys = initiator_keys.unwrap();
+        let responder_keys = responder_keys.unwrap();
+
+        assert_eq!(initiator_keys.peer, responder.node_id());
+        assert_eq!(responder_keys.peer, initiator.node_id());
+        assert_eq!(initiator_keys.tx, responder_keys.rx);
+        assert_eq!(initiator_keys.rx, responder_keys.tx);
+        assert_ne!(initiator_keys.tx, initiator_keys.rx);
+    }
+
+    #[test]
+    fn test_reject_forged_kx_key() {
+        let victim = Identity::generate();
+        let attacker = Identity::generate();
+
+        // the attacker claims the identity of the victim,
+        // but can only sign its key exchange key with its own key
+        let mut forged = SodiumKeyExchange::new(&attacker, Role::Initiator, &[]).handshake();
+        forged.additional_data[0] =
+            AdditionalDataField::new(1, victim.public_key().as_ref().to_vec());
+
+        let exchange = SodiumKeyExchange::new(&Identity::generate(), Role::Responder, &[]);
+        assert!(matches!(
+            exchange.receive_handshake(&forged),
+            Err(SessionError::InvalidSignature)
+        ));
+
+        // unsigned key exchange key
+        let mut unsigned = SodiumKeyExchange::new(&victim, Role::Initiator, &[]).handshake();
+        let (kx_public_key, _) = kx::gen_keypair();
+        let mut fake_signature = vec![0u8; sign::SIGNATUREBYTES];
+        fake_signature.extend_from_slice(kx_public_key.as_ref());
+        unsigned.additional_data[1] =
+            AdditionalDataField::new(FIELD_SIGNED_KX_PUBLIC_KEY, fake_signature);
+
+        let exchange = SodiumKeyExchange::new(&Identity::generate(), Role::Responder, &[]);
+        assert!(matches!(
+            exchange.receive_handshake(&unsigned),
+            Err(SessionError::InvalidSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fail_closed_on_bad_signature() {
+        let victim = Identity::generate();
+        let attacker = Identity::generate();
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let mut forged = SodiumKeyExchange::new(&attacker, Role::Initiator, &[]).handshake();
+        forged.additional_data[0] =
+            AdditionalDataField::new(1, victim.public_key().as_ref().to_vec());
+        write_handshake(&mut a, &forged).await.unwrap();
+
+        let result = sodium_key_exchange(&mut b, &Identity::generate(), Role::Responder, &[]).await;
+        assert!(matches!(result, Err(SessionError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_reject_transcript_mismatch() {
+        let initiator = SodiumKeyExchange::new(&Identity::generate(), Role::Initiator, b"a");
+        let responder = SodiumKeyExchange::new(&Identity::generate(), Role::Responder, b"b");
+        assert!(matches!(
+            responder.receive_handshake(&initiator.handshake()),
+            Err(SessionError::TranscriptMismatch)
+        ));
+    }
+}
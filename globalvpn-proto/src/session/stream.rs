@@ -0,0 +1,394 @@
+//! Authenticated encrypted transport of frames
+//!
+//! After the key exchange every [`Frame`] is sealed with XChaCha20-Poly1305
+//! and sent as a record.
+//!
+//! | Type  | Content                                           |
+//! | ----- | ------------------------------------------------- |
+//! | u32   | length of the following record                    |
+//! | u8    | flags                                             |
+//! | u64   | record counter                                    |
+//! | bytes | encrypted frame, including the authentication tag |
+//!
+//! Flags and counter are authenticated as additional data.
+//! The nonce is the record counter, which starts at 0 and has to increase by one
+//! with each record. Records with an unexpected counter are rejected as replays.
+//!
+//! If the [`KEY_UPDATE`] flag is set, the sender uses a new key for all following records.
+//! The new key is derived from the current key using `crypto_kdf`
+//! and the counter is reset to 0.
+
+use crate::data::NodeId;
+use crate::protocol::error::{ProtocolError, ProtocolResult};
+use crate::protocol::frame::{Frame, FrameCodec, FRAME_HEADER_LENGTH, MAX_PAYLOAD_LENGTH};
+use crate::session::keys::{SessionKey, SessionKeys};
+use bytes::{Buf, BufMut, BytesMut};
+use futures::{Sink, Stream};
+use sodiumoxide::crypto::aead::xchacha20poly1305_ietf as aead;
+use sodiumoxide::crypto::kdf::blake2b as kdf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+/// Flag of a record after which the sender switches to the next key
+pub const KEY_UPDATE: u8 = 0x01;
+
+/// Length of the record header after the length field
+const RECORD_HEADER_LENGTH: usize = 9;
+
+/// Maximum length of a record after the length field
+const MAX_RECORD_LENGTH: usize =
+    RECORD_HEADER_LENGTH + FRAME_HEADER_LENGTH + MAX_PAYLOAD_LENGTH + aead::TAGBYTES;
+
+/// Context for deriving the next key
+const REKEY_CONTEXT: [u8; kdf::CONTEXTBYTES] = *b"gvpnrkey";
+
+/// Limits after which the sending key is replaced
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RekeyPolicy {
+    /// Maximum number of plaintext bytes sealed with a single key
+    pub max_bytes: u64,
+    /// Maximum time a single key is used
+    pub max_duration: Duration,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        RekeyPolicy {
+            max_bytes: 1 << 30,
+            max_duration: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Key and counter of a single direction
+struct CipherState {
+    key: aead::Key,
+    counter: u64,
+    bytes: u64,
+    since: Instant,
+}
+
+impl CipherState {
+    fn new(key: &SessionKey) -> Self {
+        CipherState {
+            key: aead::Key(key.0),
+            counter: 0,
+            bytes: 0,
+            since: Instant::now(),
+        }
+    }
+
+    fn nonce(&self) -> aead::Nonce {
+        let mut nonce = [0u8; aead::NONCEBYTES];
+        nonce[aead::NONCEBYTES - 8..].copy_from_slice(&self.counter.to_be_bytes());
+        aead::Nonce(nonce)
+    }
+
+    fn needs_rekey(&self, policy: &RekeyPolicy) -> bool {
+        self.bytes >= policy.max_bytes
+            || self.since.elapsed() >= policy.max_duration
+            || self.counter == u64::MAX
+    }
+
+    fn rekey(&mut self) {
+        let mut next = [0u8; aead::KEYBYTES];
+        kdf::derive_from_key(&mut next, 0, REKEY_CONTEXT, &kdf::Key(self.key.0))
+            .expect("valid subkey length");
+        self.key = aead::Key(next);
+        self.counter = 0;
+        self.bytes = 0;
+        self.since = Instant::now();
+    }
+}
+
+/// Encoder and decoder sealing [`Frame`]s with the session keys
+pub struct SecureCodec {
+    frames: FrameCodec,
+    policy: RekeyPolicy,
+    tx: CipherState,
+    rx: CipherState,
+}
+
+impl SecureCodec {
+    pub fn new(keys: &SessionKeys, policy: RekeyPolicy) -> Self {
+        crate::session::init();
+        SecureCodec {
+            frames: FrameCodec::new(),
+            policy,
+            tx: CipherState::new(&keys.tx),
+            rx: CipherState::new(&keys.rx),
+        }
+    }
+}
+
+impl Encoder<Frame> for SecureCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> ProtocolResult<()> {
+        let mut plaintext = BytesMut::new();
+        self.frames.encode(&item, &mut plaintext)?;
+
+        let flags = if self.tx.needs_rekey(&self.policy) {
+            KEY_UPDATE
+        } else {
+            0
+        };
+        let mut header = [0u8; RECORD_HEADER_LENGTH];
+        header[0] = flags;
+        header[1..].copy_from_slice(&self.tx.counter.to_be_bytes());
+        let ciphertext = aead::seal(&plaintext, Some(&header), &self.tx.nonce(), &self.tx.key);
+
+        dst.reserve(4 + RECORD_HEADER_LENGTH + ciphertext.len());
+        dst.put_u32((RECORD_HEADER_LENGTH + ciphertext.len()) as u32);
+        dst.put_slice(&header);
+        dst.put_slice(&ciphertext);
+
+        self.tx.counter += 1;
+        self.tx.bytes += plaintext.len() as u64;
+        if flags & KEY_UPDATE != 0 {
+            self.tx.rekey();
+        }
+        Ok(())
+    }
+}
+
+impl Decoder for SecureCodec {
+    type Item = Frame;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> ProtocolResult<Option<Frame>> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if length > MAX_RECORD_LENGTH {
+            return Err(ProtocolError::PayloadTooLarge {
+                length,
+                max_length: MAX_RECORD_LENGTH,
+            });
+        }
+        if length < RECORD_HEADER_LENGTH + aead::TAGBYTES {
+            return Err(ProtocolError::MalformedRecord);
+        }
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(4);
+        let mut record = src.split_to(length);
+        let header = record.split_to(RECORD_HEADER_LENGTH);
+        let flags = header[0];
+        let counter = (&header[1..]).get_u64();
+        if flags & !KEY_UPDATE != 0 {
+            return Err(ProtocolError::MalformedRecord);
+        }
+        if counter != self.rx.counter {
+            return Err(ProtocolError::ReplayedRecord {
+                expected: self.rx.counter,
+                received: counter,
+            });
+        }
+        let plaintext = aead::open(&record, Some(&header), &self.rx.nonce(), &self.rx.key)
+            .map_err(|()| ProtocolError::DecryptionFailed)?;
+
+        self.rx.counter += 1;
+        if flags & KEY_UPDATE != 0 {
+            self.rx.rekey();
+        }
+
+        let mut plaintext = BytesMut::from(plaintext.as_slice());
+        match self.frames.decode(&mut plaintext)? {
+            Some(frame) if plaintext.is_empty() => Ok(Some(frame)),
+            _ => Err(ProtocolError::MalformedRecord),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> ProtocolResult<Option<Frame>> {
+        match self.decode(buf)? {
+            Some(frame) => Ok(Some(frame)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(ProtocolError::UnexpectedEof),
+        }
+    }
+}
+
+/// Stream of frames encrypted with the keys of an established session
+pub struct SecureStream<T> {
+    peer: NodeId,
+    framed: Framed<T, SecureCodec>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> SecureStream<T> {
+    pub fn new(io: T, keys: &SessionKeys, policy: RekeyPolicy) -> Self {
+        SecureStream {
+            peer: keys.peer,
+            framed: Framed::new(io, SecureCodec::new(keys, policy)),
+        }
+    }
+
+    /// NodeId of the authenticated peer
+    pub fn peer(&self) -> NodeId {
+        self.peer
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for SecureStream<T> {
+    type Item = ProtocolResult<Frame>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.framed).poll_next(cx)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Sink<Frame> for SecureStream<T> {
+    type Error = ProtocolError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<ProtocolResult<()>> {
+        Pin::new(&mut self.framed).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Frame) -> ProtocolResult<()> {
+        Pin::new(&mut self.framed).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<ProtocolResult<()>> {
+        Pin::new(&mut self.framed).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<ProtocolResult<()>> {
+        Pin::new(&mut self.framed).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::connection::{Connection, ConnectionConfig};
+    use crate::protocol::error::{ErrorPacket, ProtocolError};
+    use crate::protocol::frame::Frame;
+    use crate::session::identity::Identity;
+    use crate::session::keys::{Role, SessionKeys};
+    use crate::session::sodium::sodium_key_exchange;
+    use crate::session::stream::{RekeyPolicy, SecureCodec, SecureStream};
+    use bytes::{Bytes, BytesMut};
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    async fn session_keys() -> (SessionKeys, SessionKeys) {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let (initiator, responder) = (Identity::generate(), Identity::generate());
+        let (a, b) = tokio::join!(
+            sodium_key_exchange(&mut a, &initiator, Role::Initiator, &[]),
+            sodium_key_exchange(&mut b, &responder, Role::Responder, &[]),
+        );
+        (a.unwrap(), b.unwrap())
+    }
+
+    fn frame(data: &'static [u8]) -> Frame {
+        Frame::Error(ErrorPacket::new(0xff, 0xff, Bytes::from_static(data)))
+    }
+
+    #[tokio::test]
+    async fn test_secure_stream() {
+        let (keys_a, keys_b) = session_keys().await;
+        let (a, b) = tokio::io::duplex(1024);
+        let mut a = SecureStream::new(a, &keys_a, RekeyPolicy::default());
+        let mut b = SecureStream::new(b, &keys_b, RekeyPolicy::default());
+        assert_eq!(a.peer(), keys_a.peer);
+
+        a.send(frame(b"first")).await.unwrap();
+        a.send(Frame::Keepalive).await.unwrap();
+        b.send(frame(b"answer")).await.unwrap();
+        assert_eq!(b.next().await.unwrap().unwrap(), frame(b"first"));
+        assert_eq!(b.next().await.unwrap().unwrap(), Frame::Keepalive);
+        assert_eq!(a.next().await.unwrap().unwrap(), frame(b"answer"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_over_secure_stream() {
+        let (keys_a, keys_b) = session_keys().await;
+        let (a, b) = tokio::io::duplex(1024);
+        let a = Connection::spawn_transport(
+            SecureStream::new(a, &keys_a, RekeyPolicy::default()),
+            ConnectionConfig::default(),
+        );
+        let mut b = Connection::spawn_transport(
+            SecureStream::new(b, &keys_b, RekeyPolicy::default()),
+            ConnectionConfig::default(),
+        );
+
+        a.send(frame(b"encrypted")).await.unwrap();
+        assert_eq!(b.recv().await, Some(frame(b"encrypted")));
+        a.close().await.unwrap();
+        assert_eq!(b.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_reject_replay_and_tampering() {
+        let (keys_a, keys_b) = session_keys().await;
+        let mut sender = SecureCodec::new(&keys_a, RekeyPolicy::default());
+        let mut receiver = SecureCodec::new(&keys_b, RekeyPolicy::default());
+
+        let mut record = BytesMut::new();
+        sender.encode(frame(b"once"), &mut record).unwrap();
+        let replay = record.clone();
+        assert_eq!(receiver.decode(&mut record).unwrap(), Some(frame(b"once")));
+        assert!(matches!(
+            receiver.decode(&mut replay.clone()),
+            Err(ProtocolError::ReplayedRecord {
+                expected: 1,
+                received: 0
+            })
+        ));
+
+        let mut tampered = BytesMut::new();
+        sender.encode(frame(b"twice"), &mut tampered).unwrap();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        assert!(matches!(
+            receiver.decode(&mut tampered),
+            Err(ProtocolError::DecryptionFailed)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rekey() {
+        let (keys_a, keys_b) = session_keys().await;
+        let policy = RekeyPolicy {
+            max_bytes: 16,
+            max_duration: Duration::from_secs(10),
+        };
+        let mut sender = SecureCodec::new(&keys_a, policy);
+        let mut receiver = SecureCodec::new(&keys_b, RekeyPolicy::default());
+
+        let mut buf = BytesMut::new();
+        // byte budget
+        for _ in 0..5 {
+            sender.encode(frame(b"0123456789"), &mut buf).unwrap();
+        }
+        // time budget
+        tokio::time::advance(Duration::from_secs(11)).await;
+        for _ in 0..2 {
+            sender.encode(frame(b"late"), &mut buf).unwrap();
+        }
+        // the first late record carried the key update
+        assert_eq!(sender.tx.counter, 1);
+
+        for _ in 0..5 {
+            assert_eq!(
+                receiver.decode(&mut buf).unwrap(),
+                Some(frame(b"0123456789"))
+            );
+        }
+        for _ in 0..2 {
+            assert_eq!(receiver.decode(&mut buf).unwrap(), Some(frame(b"late")));
+        }
+        assert_eq!(receiver.rx.counter, 1);
+        assert_eq!(receiver.rx.key, sender.tx.key);
+    }
+}
@@ -0,0 +1,421 @@
+//! Negotiation of the crypto protocol
+//!
+//! 1. Both sides send a handshake packet listing the ids of all supported
+//!    crypto suites, strongest first.
+//! 2. Both sides pick the strongest suite supported by both of them.
+//!    The strength of a suite is defined together with its id,
+//!    so both sides pick the same suite.
+//! 3. The selected suite establishes the session keys.
+//!    It has to authenticate the negotiation transcript, which consists of the encoded
+//!    handshake packet of the initiator followed by the one of the responder.
+//!    A downgrade by removing suites from one of the lists is detected this way.
+//!
+//! If the strongest suite of the initiator can start early, the initiator announces it
+//! in additional data field 3, containing the suite id, and sends the first message of
+//! the suite right after its handshake packet, prepended with its length as u16.
+//! If the responder selects that suite, it answers the early message right after its
+//! own handshake packet, so the session is established in a single round trip.
+//! Otherwise the early message is discarded and the selected suite runs as usual.
+
+use crate::session::error::{SessionError, SessionResult};
+use crate::session::handshake::{
+    read_handshake, write_handshake, AdditionalDataField, HandshakePacket,
+};
+use crate::session::identity::Identity;
+use crate::session::keys::{Role, SessionKeys};
+use crate::session::noise::NoiseIkSuite;
+use crate::session::sodium::{sodium_key_exchange, CRYPTO_PROTOCOL_SODIUM};
+use bytes::BytesMut;
+use futures::future::BoxFuture;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Additional data field containing the id of the suite started early by the initiator
+pub const FIELD_EARLY_START: u16 = 3;
+
+/// Stream a session can be established on
+pub trait SessionIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> SessionIo for T {}
+
+/// A crypto protocol which can establish session keys
+pub trait CryptoSuite: Send + Sync {
+    /// Id of the crypto protocol in the handshake packet
+    fn id(&self) -> u8;
+
+    /// Strength of the suite, the strongest common suite is selected
+    fn strength(&self) -> u8;
+
+    /// Whether the suite can be used in the given role
+    ///
+    /// Suites which need prior knowledge about the peer are only offered
+    /// if that knowledge is available.
+    fn supports(&self, _role: Role) -> bool {
+        true
+    }
+
+    /// Establishes the session keys after the suite has been selected
+    ///
+    /// Implementations have to fail if the peer saw a different `transcript`.
+    fn establish<'a>(
+        &'a self,
+        io: &'a mut dyn SessionIo,
+        identity: &'a Identity,
+        role: Role,
+        transcript: &'a [u8],
+    ) -> BoxFuture<'a, SessionResult<SessionKeys>>;
+
+    /// Whether the initiator can send the first message before the suite was selected
+    fn starts_early(&self) -> bool {
+        false
+    }
+
+    /// Starts the suite as initiator before the negotiation finished
+    ///
+    /// `handshake` is the encoded handshake packet of the initiator.
+    /// Returns the first message, which is sent right after the handshake packet.
+    fn start_early(
+        &self,
+        _identity: &Identity,
+        _handshake: &[u8],
+    ) -> SessionResult<(Vec<u8>, Box<dyn EarlyHandshake>)> {
+        Err(SessionError::MalformedHandshake)
+    }
+
+    /// Establishes the session keys as responder of an early started suite
+    ///
+    /// `message` is the first message of the initiator, `handshake` the encoded
+    /// handshake packet of the initiator.
+    /// Implementations have to prove to the initiator that they saw the same `transcript`.
+    fn establish_early<'a>(
+        &'a self,
+        _io: &'a mut dyn SessionIo,
+        _identity: &'a Identity,
+        _message: &'a [u8],
+        _handshake: &'a [u8],
+        _transcript: &'a [u8],
+    ) -> BoxFuture<'a, SessionResult<SessionKeys>> {
+        Box::pin(async { Err(SessionError::MalformedHandshake) })
+    }
+}
+
+/// Suite started early by the initiator, waiting for the answer of the responder
+pub trait EarlyHandshake: Send {
+    /// Finishes the handshake after the responder selected the suite
+    ///
+    /// Implementations have to fail if the responder saw a different `transcript`.
+    fn finish<'a>(
+        self: Box<Self>,
+        io: &'a mut dyn SessionIo,
+        transcript: &'a [u8],
+    ) -> BoxFuture<'a, SessionResult<SessionKeys>>;
+}
+
+/// Sodium key exchange (crypto protocol 1)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SodiumSuite;
+
+impl CryptoSuite for SodiumSuite {
+    fn id(&self) -> u8 {
+        CRYPTO_PROTOCOL_SODIUM
+    }
+
+    fn strength(&self) -> u8 {
+        10
+    }
+
+    fn establish<'a>(
+        &'a self,
+        io: &'a mut dyn SessionIo,
+        identity: &'a Identity,
+        role: Role,
+        transcript: &'a [u8],
+    ) -> BoxFuture<'a, SessionResult<SessionKeys>> {
+        Box::pin(sodium_key_exchange(io, identity, role, transcript))
+    }
+}
+
+/// Set of crypto suites supported by a node
+#[derive(Clone)]
+pub struct CryptoSuites {
+    suites: Vec<Arc<dyn CryptoSuite>>,
+}
+
+impl Default for CryptoSuites {
+    fn default() -> Self {
+        CryptoSuites::new()
+            .with(SodiumSuite)
+            .with(NoiseIkSuite::new())
+    }
+}
+
+impl fmt::Debug for CryptoSuites {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CryptoSuites")
+            .field(
+                "ids",
+                &self
+                    .suites
+                    .iter()
+                    .map(|suite| suite.id())
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl CryptoSuites {
+    /// Creates an empty set of suites
+    pub fn new() -> Self {
+        CryptoSuites { suites: Vec::new() }
+    }
+
+    /// Adds a suite, replacing a suite with the same id
+    pub fn with(mut self, suite: impl CryptoSuite + 'static) -> Self {
+        self.suites.retain(|existing| existing.id() != suite.id());
+        self.suites.push(Arc::new(suite));
+        self.suites
+            .sort_by_key(|suite| (std::cmp::Reverse(suite.strength()), suite.id()));
+        self
+    }
+
+    /// Ids of all suites usable in `role`, strongest first
+    pub fn ids(&self, role: Role) -> Vec<u8> {
+        self.usable(role).map(|suite| suite.id()).collect()
+    }
+
+    /// Selects the strongest suite usable in `role` which is also supported by the peer
+    pub fn negotiate(&self, role: Role, peer_ids: &[u8]) -> SessionResult<&Arc<dyn CryptoSuite>> {
+        self.usable(role)
+            .find(|suite| peer_ids.contains(&suite.id()))
+            .ok_or(SessionError::NoCommonCryptoProtocol)
+    }
+
+    fn usable(&self, role: Role) -> impl Iterator<Item = &Arc<dyn CryptoSuite>> {
+        self.suites.iter().filter(move |suite| suite.supports(role))
+    }
+}
+
+/// Negotiates a crypto suite and establishes a session over a stream
+pub async fn establish<T>(
+    io: &mut T,
+    identity: &Identity,
+    role: Role,
+    suites: &CryptoSuites,
+) -> SessionResult<SessionKeys>
+where
+    T: SessionIo,
+{
+    let mut local = HandshakePacket::new(suites.ids(role));
+    let early_suite = match role {
+        Role::Initiator => suites
+            .usable(role)
+            .next()
+            .filter(|suite| suite.starts_early()),
+        Role::Responder => None,
+    };
+    if let Some(suite) = early_suite {
+        local.additional_data.push(AdditionalDataField::new(
+            FIELD_EARLY_START,
+            vec![suite.id()],
+        ));
+    }
+    let mut local_encoded = BytesMut::new();
+    local.encode(&mut local_encoded)?;
+
+    write_handshake(io, &local).await?;
+    let started = match early_suite {
+        Some(suite) => {
+            let (message, handshake) = suite.start_early(identity, &local_encoded)?;
+            write_early_message(io, &message).await?;
+            Some((suite.id(), handshake))
+        }
+        None => None,
+    };
+
+    let peer = read_handshake(io).await?;
+    let early_message = match (role, peer.field(FIELD_EARLY_START)) {
+        (Role::Responder, Some(field)) => {
+            if field.len() != 1 {
+                return Err(SessionError::MalformedField(FIELD_EARLY_START));
+            }
+            Some((field[0], read_early_message(io).await?))
+        }
+        _ => None,
+    };
+    let suite = suites.negotiate(role, &peer.crypto_protocols)?;
+
+    let mut transcript = BytesMut::new();
+    let mut peer_encoded = BytesMut::new();
+    peer.encode(&mut peer_encoded)?;
+    let (first, second) = match role {
+        Role::Initiator => (&local_encoded, &peer_encoded),
+        Role::Responder => (&peer_encoded, &local_encoded),
+    };
+    transcript.extend_from_slice(first);
+    transcript.extend_from_slice(second);
+
+    if let Some((id, handshake)) = started {
+        if id == suite.id() {
+            return handshake.finish(io, &transcript).await;
+        }
+    }
+    if let Some((id, message)) = early_message {
+        if id == suite.id() {
+            return suite
+                .establish_early(io, identity, &message, &peer_encoded, &transcript)
+                .await;
+        }
+    }
+    suite.establish(io, identity, role, &transcript).await
+}
+
+async fn write_early_message<T>(stream: &mut T, message: &[u8]) -> SessionResult<()>
+where
+    T: AsyncWrite + Unpin + ?Sized,
+{
+    let length = u16::try_from(message.len()).map_err(|_err| SessionError::MalformedHandshake)?;
+    stream.write_u16(length).await?;
+    stream.write_all(message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_early_message<T>(stream: &mut T) -> SessionResult<Vec<u8>>
+where
+    T: AsyncRead + Unpin + ?Sized,
+{
+    let length = stream.read_u16().await? as usize;
+    let mut message = vec![0u8; length];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::error::{SessionError, SessionResult};
+    use crate::session::handshake::{read_handshake, write_handshake};
+    use crate::session::identity::Identity;
+    use crate::session::keys::{Role, SessionKeys};
+    use crate::session::suite::{establish, CryptoSuite, CryptoSuites, SessionIo, SodiumSuite};
+    use futures::future::BoxFuture;
+    use std::sync::{Arc, Mutex};
+
+    /// Suite which records the selection and delegates to the Sodium key exchange
+    struct TestSuite {
+        id: u8,
+        strength: u8,
+        selected: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl CryptoSuite for TestSuite {
+        fn id(&self) -> u8 {
+            self.id
+        }
+
+        fn strength(&self) -> u8 {
+            self.strength
+        }
+
+        fn establish<'a>(
+            &'a self,
+            io: &'a mut dyn SessionIo,
+            identity: &'a Identity,
+            role: Role,
+            transcript: &'a [u8],
+        ) -> BoxFuture<'a, SessionResult<SessionKeys>> {
+            self.selected.lock().unwrap().push(self.id);
+            SodiumSuite.establish(io, identity, role, transcript)
+        }
+    }
+
+    fn suites(ids: &[(u8, u8)], selected: &Arc<Mutex<Vec<u8>>>) -> CryptoSuites {
+        ids.iter()
+            .fold(CryptoSuites::new(), |suites, &(id, strength)| {
+                suites.with(TestSuite {
+                    id,
+                    strength,
+                    selected: selected.clone(),
+                })
+            })
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let selected = Arc::new(Mutex::new(Vec::new()));
+        let local = suites(&[(1, 10), (2, 30), (3, 20)], &selected);
+        assert_eq!(local.ids(Role::Initiator), vec![2, 3, 1]);
+        assert_eq!(local.negotiate(Role::Initiator, &[1, 3]).unwrap().id(), 3);
+        assert_eq!(
+            local.negotiate(Role::Initiator, &[1, 3, 2]).unwrap().id(),
+            2
+        );
+        assert!(matches!(
+            local.negotiate(Role::Initiator, &[4]),
+            Err(SessionError::NoCommonCryptoProtocol)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_establish_strongest_common_suite() {
+        let selected = Arc::new(Mutex::new(Vec::new()));
+        let a_suites = suites(&[(1, 10), (2, 30), (3, 20)], &selected);
+        let b_suites = suites(&[(1, 10), (3, 20)], &selected);
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let (mut a, mut b) = tokio::io::duplex(1024);
+
+        let (a_keys, b_keys) = tokio::join!(
+            establish(&mut a, &a_identity, Role::Initiator, &a_suites),
+            establish(&mut b, &b_identity, Role::Responder, &b_suites),
+        );
+        assert_eq!(a_keys.unwrap().peer, b_identity.node_id());
+        assert_eq!(b_keys.unwrap().peer, a_identity.node_id());
+        assert_eq!(*selected.lock().unwrap(), vec![3, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_detect_downgrade() {
+        let selected = Arc::new(Mutex::new(Vec::new()));
+        let a_suites = suites(&[(1, 10), (2, 30)], &selected);
+        let b_suites = suites(&[(1, 10), (2, 30)], &selected);
+        let (a_identity, b_identity) = (Identity::generate(), Identity::generate());
+        let (mut a, mut mitm_a) = tokio::io::duplex(1024);
+        let (mut mitm_b, mut b) = tokio::io::duplex(1024);
+
+        // the attacker removes the strongest suite from both lists,
+        // so both sides select the weaker suite
+        let mitm = async {
+            let mut packet = read_handshake(&mut mitm_a).await.unwrap();
+            assert_eq!(packet.crypto_protocols, vec![2, 1]);
+            packet.crypto_protocols.retain(|&id| id != 2);
+            write_handshake(&mut mitm_b, &packet).await.unwrap();
+            let mut packet = read_handshake(&mut mitm_b).await.unwrap();
+            packet.crypto_protocols.retain(|&id| id != 2);
+            write_handshake(&mut mitm_a, &packet).await.unwrap();
+            // relay the remaining data unchanged
+            let (mut a_read, mut a_write) = tokio::io::split(mitm_a);
+            let (mut b_read, mut b_write) = tokio::io::split(mitm_b);
+            let _ = tokio::join!(
+                tokio::io::copy(&mut a_read, &mut b_write),
+                tokio::io::copy(&mut b_read, &mut a_write),
+            );
+        };
+        let client = async {
+            let result = establish(&mut a, &a_identity, Role::Initiator, &a_suites).await;
+            drop(a);
+            result
+        };
+        let server = async {
+            let result = establish(&mut b, &b_identity, Role::Responder, &b_suites).await;
+            drop(b);
+            result
+        };
+        let (a_keys, b_keys, ()) = tokio::join!(client, server, mitm);
+        // the weaker suite detects the manipulated transcript
+        assert_eq!(*selected.lock().unwrap(), vec![1, 1]);
+        assert!(matches!(a_keys, Err(SessionError::TranscriptMismatch)));
+        assert!(matches!(b_keys, Err(SessionError::TranscriptMismatch)));
+    }
+}
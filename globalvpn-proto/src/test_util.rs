@@ -0,0 +1,93 @@
+//! Fixtures shared by the tests of all modules
+
+use crate::certificate::{
+    CertificateData, Clock, NodeIpReachability, NodeMetadata, NodeReachabilityInformation,
+    RawCertificate,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use ring::rand::SystemRandom;
+use std::cell::Cell;
+
+/// Clock which is set by the test
+pub struct TestClock(pub Cell<DateTime<Utc>>);
+
+impl TestClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        TestClock(Cell::new(now))
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0.get()
+    }
+}
+
+/// Fixed point in time tests using a [`TestClock`] start at
+pub fn start() -> DateTime<Utc> {
+    Utc.ymd(2021, 1, 1).and_hms(12, 0, 0)
+}
+
+/// Generates a PKCS#8 encoded Ed25519 private key
+pub fn private_key() -> Vec<u8> {
+    let rng = SystemRandom::new();
+    ring::signature::Ed25519KeyPair::generate_pkcs8(&rng)
+        .unwrap()
+        .as_ref()
+        .to_vec()
+}
+
+/// Reachability information of a node listening on `[2001:db8::1]:1337`
+pub fn reachability() -> NodeReachabilityInformation {
+    NodeReachabilityInformation {
+        network_reachability: vec![NodeIpReachability {
+            address: "2001:db8::1".parse().unwrap(),
+            quic_port: Some(1337),
+        }]
+        .into_iter()
+        .collect(),
+        ..NodeReachabilityInformation::default()
+    }
+}
+
+/// Certificate data of a reachable node
+pub fn data(metadata: NodeMetadata) -> CertificateData {
+    CertificateData {
+        reachability: reachability(),
+        metadata,
+    }
+}
+
+/// Signs the certificate of a reachable node, valid for one day starting at `not_before`
+pub fn sign(
+    private_key: &[u8],
+    metadata: NodeMetadata,
+    not_before: DateTime<Utc>,
+) -> RawCertificate {
+    sign_data(private_key, &data(metadata), not_before)
+}
+
+/// Signs certificate data, valid for one day starting at `not_before`
+pub fn sign_data(
+    private_key: &[u8],
+    data: &CertificateData,
+    not_before: DateTime<Utc>,
+) -> RawCertificate {
+    data.sign_with_validity(private_key, not_before, Duration::days(1))
+        .unwrap()
+}
+
+/// Signs the certificate of a reachable node with default metadata,
+/// valid from `seconds` after now
+pub fn sign_from_now(private_key: &[u8], seconds: i64) -> RawCertificate {
+    sign(
+        private_key,
+        NodeMetadata::default(),
+        Utc::now() + Duration::seconds(seconds),
+    )
+}
+
+/// Certificate of a new reachable node with default metadata, valid from now
+pub fn certificate() -> RawCertificate {
+    sign_from_now(&private_key(), 0)
+}